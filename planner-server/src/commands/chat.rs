@@ -0,0 +1,109 @@
+use super::{AnnounceTo, AnnounceType, ErrorType, ProcessReceive};
+use crate::state::{
+    chat::{ChatMessage, MAX_CHAT_MESSAGE_LEN},
+    unix_millis,
+    user::AccessLevel,
+    App,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    net::SocketAddr,
+    sync::{Arc, RwLock},
+};
+use uuid::Uuid;
+
+/// Sends a message to everyone in the room.
+#[derive(Deserialize)]
+pub struct Receive {
+    content: String,
+}
+impl ProcessReceive for Receive {
+    fn process(
+        self,
+        app: Arc<RwLock<App>>,
+        room_name: &str,
+        addr: SocketAddr,
+    ) -> Result<AnnounceTo, ErrorType> {
+        let content = self.content.trim();
+        if content.is_empty() {
+            return Error::EmptyMessage.into();
+        }
+        if content.len() > MAX_CHAT_MESSAGE_LEN {
+            return Error::MessageTooLong {
+                max_len: MAX_CHAT_MESSAGE_LEN,
+            }
+            .into();
+        }
+
+        let mut app_write_lock = app.write().unwrap();
+        let Some(room) = app_write_lock.get_room_mut(room_name) else {
+            return Error::RoomDoesNotExist.into();
+        };
+        let Some(user) = room.get_user_from_addr(addr) else {
+            return Error::RoomDoesNotExist.into();
+        };
+        if !room.get_config().can_chat(user.access_level) {
+            return Error::NoPermission.into();
+        }
+
+        let message = ChatMessage {
+            uuid: Uuid::new_v4(),
+            user: user.uuid,
+            username: user.username.clone(),
+            content: content.to_string(),
+            sent_at: unix_millis(),
+        };
+        room.add_chat_message(message.clone());
+
+        Ok(AnnounceType::Chat { message }.announce_to_all())
+    }
+}
+
+/// Deletes a message from the chat. Only the admin can do this.
+#[derive(Deserialize)]
+pub struct ReceiveDelete {
+    message: Uuid,
+}
+impl ProcessReceive for ReceiveDelete {
+    fn process(
+        self,
+        app: Arc<RwLock<App>>,
+        room_name: &str,
+        addr: SocketAddr,
+    ) -> Result<AnnounceTo, ErrorType> {
+        let mut app_write_lock = app.write().unwrap();
+        let Some(room) = app_write_lock.get_room_mut(room_name) else {
+            return Error::RoomDoesNotExist.into();
+        };
+        let Some(user) = room.get_user_from_addr(addr) else {
+            return Error::RoomDoesNotExist.into();
+        };
+        if user.access_level != AccessLevel::Admin {
+            return Error::NoPermission.into();
+        }
+
+        let deleted_by = user.uuid;
+        if !room.delete_chat_message(self.message) {
+            return Error::MessageDoesNotExist.into();
+        }
+
+        Ok(AnnounceType::ChatMessageDeleted {
+            message: self.message,
+            deleted_by,
+        }
+        .announce_to_all())
+    }
+}
+
+#[derive(Serialize)]
+#[serde(tag = "code", rename_all = "snake_case")]
+pub enum Error {
+    RoomDoesNotExist,
+    NoPermission,
+    /// The message was empty or only whitespace.
+    EmptyMessage,
+    MessageTooLong {
+        max_len: usize,
+    },
+    MessageDoesNotExist,
+}
@@ -0,0 +1,208 @@
+use super::{AnnounceTo, AnnounceType, ErrorType, ProcessReceive};
+use crate::state::{
+    comment::{Comment, Thread, MAX_CANVAS_THREADS, MAX_COMMENT_LEN, MAX_THREAD_COMMENTS},
+    unix_millis, App, RoomUser,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    net::SocketAddr,
+    sync::{Arc, RwLock},
+};
+use uuid::Uuid;
+
+/// Starts a comment thread about an element on the sender's canvas.
+#[derive(Deserialize)]
+pub struct ReceiveCreateThread {
+    element: Uuid,
+    content: String,
+}
+impl ProcessReceive for ReceiveCreateThread {
+    fn process(
+        self,
+        app: Arc<RwLock<App>>,
+        room_name: &str,
+        addr: SocketAddr,
+    ) -> Result<AnnounceTo, ErrorType> {
+        let content = check_content(&self.content)?;
+
+        let mut app_write_lock = app.write().unwrap();
+        let Some(room) = app_write_lock.get_room_mut(room_name) else {
+            return Error::RoomDoesNotExist.into();
+        };
+        let Some(user) = room.get_user_from_addr(addr) else {
+            return Error::RoomDoesNotExist.into();
+        };
+        if !room.get_config().can_chat(user.access_level) {
+            return Error::NoPermission.into();
+        }
+        let comment = new_comment(user, content);
+        let canvas_id = user.canvas;
+
+        let canvas = room.get_or_create_canvas(canvas_id);
+        if canvas.get_element(&self.element).is_none() {
+            return Error::ElementDoesNotExist.into();
+        }
+        if canvas.threads.len() >= MAX_CANVAS_THREADS {
+            return Error::TooManyThreads {
+                max: MAX_CANVAS_THREADS,
+            }
+            .into();
+        }
+        let thread = Thread {
+            uuid: Uuid::new_v4(),
+            element: self.element,
+            comments: vec![comment],
+            resolved_by: None,
+            archived: false,
+        };
+        canvas.threads.push(thread.clone());
+
+        Ok(AnnounceType::ThreadChanged { thread }.announce_to_canvas(canvas_id))
+    }
+}
+
+/// Adds a comment to a thread on the sender's canvas.
+#[derive(Deserialize)]
+pub struct ReceiveReply {
+    thread: Uuid,
+    content: String,
+}
+impl ProcessReceive for ReceiveReply {
+    fn process(
+        self,
+        app: Arc<RwLock<App>>,
+        room_name: &str,
+        addr: SocketAddr,
+    ) -> Result<AnnounceTo, ErrorType> {
+        let content = check_content(&self.content)?;
+
+        let mut app_write_lock = app.write().unwrap();
+        let Some(room) = app_write_lock.get_room_mut(room_name) else {
+            return Error::RoomDoesNotExist.into();
+        };
+        let Some(user) = room.get_user_from_addr(addr) else {
+            return Error::RoomDoesNotExist.into();
+        };
+        if !room.get_config().can_chat(user.access_level) {
+            return Error::NoPermission.into();
+        }
+        let comment = new_comment(user, content);
+        let canvas_id = user.canvas;
+
+        let Some(thread) = room
+            .get_or_create_canvas(canvas_id)
+            .get_thread_mut(&self.thread)
+        else {
+            return Error::ThreadDoesNotExist.into();
+        };
+        if thread.archived {
+            return Error::ThreadArchived.into();
+        }
+        if thread.comments.len() >= MAX_THREAD_COMMENTS {
+            return Error::TooManyComments {
+                max: MAX_THREAD_COMMENTS,
+            }
+            .into();
+        }
+        thread.comments.push(comment);
+
+        Ok(AnnounceType::ThreadChanged {
+            thread: thread.clone(),
+        }
+        .announce_to_canvas(canvas_id))
+    }
+}
+
+/// Marks a thread on the sender's canvas as resolved, or as not resolved.
+#[derive(Deserialize)]
+pub struct ReceiveResolve {
+    thread: Uuid,
+    resolved: bool,
+}
+impl ProcessReceive for ReceiveResolve {
+    fn process(
+        self,
+        app: Arc<RwLock<App>>,
+        room_name: &str,
+        addr: SocketAddr,
+    ) -> Result<AnnounceTo, ErrorType> {
+        let mut app_write_lock = app.write().unwrap();
+        let Some(room) = app_write_lock.get_room_mut(room_name) else {
+            return Error::RoomDoesNotExist.into();
+        };
+        let Some(user) = room.get_user_from_addr(addr) else {
+            return Error::RoomDoesNotExist.into();
+        };
+        if !room.get_config().can_chat(user.access_level) {
+            return Error::NoPermission.into();
+        }
+        let user_uuid = user.uuid;
+        let canvas_id = user.canvas;
+
+        let Some(thread) = room
+            .get_or_create_canvas(canvas_id)
+            .get_thread_mut(&self.thread)
+        else {
+            return Error::ThreadDoesNotExist.into();
+        };
+        if thread.archived {
+            return Error::ThreadArchived.into();
+        }
+        thread.resolved_by = self.resolved.then_some(user_uuid);
+
+        Ok(AnnounceType::ThreadChanged {
+            thread: thread.clone(),
+        }
+        .announce_to_canvas(canvas_id))
+    }
+}
+
+/// Checks a comment isn't empty or too long, returning it without any
+/// surrounding whitespace.
+fn check_content(content: &str) -> Result<&str, ErrorType> {
+    let content = content.trim();
+    if content.is_empty() {
+        return Error::EmptyComment.into();
+    }
+    if content.len() > MAX_COMMENT_LEN {
+        return Error::CommentTooLong {
+            max_len: MAX_COMMENT_LEN,
+        }
+        .into();
+    }
+    Ok(content)
+}
+
+fn new_comment(author: &RoomUser, content: &str) -> Comment {
+    Comment {
+        uuid: Uuid::new_v4(),
+        author: author.uuid,
+        author_name: author.username.clone(),
+        content: content.to_string(),
+        created_at: unix_millis(),
+    }
+}
+
+#[derive(Serialize)]
+#[serde(tag = "code", rename_all = "snake_case")]
+pub enum Error {
+    RoomDoesNotExist,
+    NoPermission,
+    /// The element isn't on the sender's canvas.
+    ElementDoesNotExist,
+    /// The thread isn't on the sender's canvas.
+    ThreadDoesNotExist,
+    /// The thread's element was deleted, so it can't be changed.
+    ThreadArchived,
+    /// The comment was empty or only whitespace.
+    EmptyComment,
+    CommentTooLong {
+        max_len: usize,
+    },
+    TooManyComments {
+        max: usize,
+    },
+    TooManyThreads {
+        max: usize,
+    },
+}
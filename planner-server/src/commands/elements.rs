@@ -1,5 +1,10 @@
 use super::{AnnounceTo, AnnounceType, ErrorType, ProcessReceive};
-use crate::state::{element::Element, user::AccessLevel, App};
+use crate::state::{
+    element::{Element, ElementPatch, PatchError, ValidationError},
+    history::ElementChange,
+    user::AccessLevel,
+    App,
+};
 use serde::{Deserialize, Serialize};
 use std::{
     net::SocketAddr,
@@ -23,8 +28,20 @@ impl ProcessReceive for Receive {
         let mut sender_elements = vec![];
         let mut deleted_elements = vec![];
         let mut sender_deleted_elements = vec![];
+        let mut changes = vec![];
+        let mut threads = vec![];
         let canvas_id;
 
+        for sent_el in &self.elements {
+            if let Err(error) = sent_el.validate() {
+                return Error::InvalidElement {
+                    element: sent_el.uuid,
+                    error,
+                }
+                .into();
+            }
+        }
+
         {
             let mut app_write_lock = app.write().unwrap();
             let Some(room) = app_write_lock.get_room_mut(room_name) else {
@@ -41,10 +58,20 @@ impl ProcessReceive for Receive {
 
             // Change the selected elements
             let canvas = room.get_or_create_canvas(user.canvas);
-            for sent_el in self.elements {
+            for mut sent_el in self.elements {
                 if let Some(known_el) = canvas.get_element_mut(&sent_el.uuid) {
                     let is_selected = known_el.selected_by.is_none_or(|u| u == user_uuid);
-                    if is_selected && user_can_make_changes {
+                    // Reject changes made to an older version of the element,
+                    // as they would undo whatever changed since.
+                    let is_current =
+                        sent_el.version.is_none() || sent_el.version == known_el.version;
+                    if is_selected && is_current && user_can_make_changes {
+                        sent_el.keep_server_owned_fields(known_el);
+                        sent_el.mark_edited(user_uuid);
+                        changes.push(ElementChange {
+                            before: Some(known_el.clone()),
+                            after: Some(sent_el.clone()),
+                        });
                         *known_el = sent_el.clone();
                         elements.push(sent_el.clone());
                         sender_elements.push(sent_el);
@@ -55,6 +82,11 @@ impl ProcessReceive for Receive {
                 } else {
                     // Creating this element.
                     if user_can_make_changes {
+                        sent_el.mark_created(user_uuid);
+                        changes.push(ElementChange {
+                            before: None,
+                            after: Some(sent_el.clone()),
+                        });
                         canvas.add_element(sent_el.clone());
                         elements.push(sent_el.clone());
                         sender_elements.push(sent_el);
@@ -69,7 +101,11 @@ impl ProcessReceive for Receive {
                 if let Some(known_el) = canvas.get_element(&sent_deleted_uuid) {
                     let is_selected = known_el.selected_by.is_none_or(|u| u == user_uuid);
                     if is_selected && user_can_make_changes {
-                        canvas.delete_element(&sent_deleted_uuid);
+                        changes.push(ElementChange {
+                            before: Some(known_el.clone()),
+                            after: None,
+                        });
+                        threads.extend(canvas.delete_element(&sent_deleted_uuid));
                         deleted_elements.push(sent_deleted_uuid);
                         sender_deleted_elements.push(sent_deleted_uuid);
                     } else {
@@ -80,12 +116,16 @@ impl ProcessReceive for Receive {
                     // Element is already deleted?
                 }
             }
+
+            canvas.history.record(user_uuid, changes);
         }
 
         if elements.is_empty() && deleted_elements.is_empty() {
             Ok(AnnounceType::ElementsChanged {
                 elements: sender_elements,
                 deleted_elements: sender_deleted_elements,
+                patched_elements: vec![],
+                threads: vec![],
             }
             .respond_to_sender())
         } else {
@@ -93,10 +133,138 @@ impl ProcessReceive for Receive {
                 respond: AnnounceType::ElementsChanged {
                     elements: sender_elements,
                     deleted_elements: sender_deleted_elements,
+                    patched_elements: vec![],
+                    threads: threads.clone(),
                 },
                 announce: AnnounceType::ElementsChanged {
                     elements,
                     deleted_elements,
+                    patched_elements: vec![],
+                    threads,
+                },
+                canvas: canvas_id,
+            })
+        }
+    }
+}
+
+/// Changes only some fields of elements, rather than sending the whole
+/// element like [`Receive`] does. This lets users edit different fields of
+/// the same element without overwriting each other's changes.
+///
+/// Only the patches are announced to other users.
+#[derive(Deserialize)]
+pub struct ReceivePatch {
+    patches: Vec<ElementPatch>,
+}
+impl ProcessReceive for ReceivePatch {
+    fn process(
+        self,
+        app: Arc<RwLock<App>>,
+        room_name: &str,
+        addr: SocketAddr,
+    ) -> Result<AnnounceTo, ErrorType> {
+        let mut patched_elements = vec![];
+        let mut sender_elements = vec![];
+        let mut sender_deleted_elements = vec![];
+        let mut changes = vec![];
+        let canvas_id;
+
+        {
+            let mut app_write_lock = app.write().unwrap();
+            let Some(room) = app_write_lock.get_room_mut(room_name) else {
+                return Error::RoomDoesNotExist.into();
+            };
+            let Some(user) = room.get_user_from_addr(addr) else {
+                // not sure when this would happen but dont feel comfortable
+                // with an unwrap
+                return Error::RoomDoesNotExist.into();
+            };
+            if user.access_level == AccessLevel::View {
+                return Error::NoPermission.into();
+            }
+            let user_uuid = user.uuid;
+            canvas_id = user.canvas;
+            let canvas = room.get_or_create_canvas(user.canvas);
+
+            // Apply every patch before changing anything, so one bad patch
+            // doesn't leave the rest half-applied. Patches to the same element
+            // are applied in order, each to the result of the one before.
+            let mut accepted: Vec<(ElementPatch, Element)> = vec![];
+            for mut patch in self.patches {
+                let Some(known_el) = canvas.get_element(&patch.uuid) else {
+                    // Tell the sender it doesn't exist anymore
+                    sender_deleted_elements.push(patch.uuid);
+                    continue;
+                };
+                let base = accepted
+                    .iter()
+                    .rev()
+                    .find(|(_, el)| el.uuid == patch.uuid)
+                    .map_or(known_el, |(_, el)| el);
+                let is_selected_by_other = base.selected_by.is_some_and(|u| u != user_uuid);
+                let is_outdated = patch.version.is_some() && patch.version != base.version;
+                if is_selected_by_other || is_outdated {
+                    // Tell the sender to revert the changes
+                    sender_elements.push(base.clone());
+                    continue;
+                }
+                let mut el = match base.patched(&patch) {
+                    Ok(el) => el,
+                    Err(error) => {
+                        return Error::InvalidPatch {
+                            element: patch.uuid,
+                            error,
+                        }
+                        .into();
+                    }
+                };
+                if let Err(error) = el.validate() {
+                    return Error::InvalidElement {
+                        element: el.uuid,
+                        error,
+                    }
+                    .into();
+                }
+                el.mark_edited(user_uuid);
+                patch.version = el.version;
+                accepted.push((patch, el));
+            }
+
+            for (patch, el) in accepted {
+                if let Some(known_el) = canvas.get_element_mut(&el.uuid) {
+                    changes.push(ElementChange {
+                        before: Some(known_el.clone()),
+                        after: Some(el.clone()),
+                    });
+                    *known_el = el;
+                    patched_elements.push(patch);
+                }
+            }
+            canvas.history.record_patch(user_uuid, changes);
+        }
+
+        if patched_elements.is_empty() {
+            Ok(AnnounceType::ElementsChanged {
+                elements: sender_elements,
+                deleted_elements: sender_deleted_elements,
+                patched_elements: vec![],
+                threads: vec![],
+            }
+            .respond_to_sender())
+        } else {
+            Ok(AnnounceTo::ResponseAndAnnounceToCanvas {
+                respond: AnnounceType::ElementsChanged {
+                    elements: sender_elements,
+                    deleted_elements: sender_deleted_elements,
+                    patched_elements: patched_elements.clone(),
+                    threads: vec![],
+                },
+                announce: AnnounceType::ElementsChanged {
+                    elements: vec![],
+                    deleted_elements: vec![],
+                    patched_elements,
+                    threads: vec![],
                 },
                 canvas: canvas_id,
             })
@@ -109,4 +277,16 @@ impl ProcessReceive for Receive {
 pub enum Error {
     NoPermission,
     RoomDoesNotExist,
+    /// An element had an invalid field. None of the changes were made.
+    InvalidElement {
+        element: Uuid,
+        #[serde(flatten)]
+        error: ValidationError,
+    },
+    /// A patch couldn't be applied to an element. None of the patches were
+    /// applied.
+    InvalidPatch {
+        element: Uuid,
+        error: PatchError,
+    },
 }
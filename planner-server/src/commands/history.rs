@@ -0,0 +1,102 @@
+use super::{AnnounceTo, AnnounceType, ErrorType, ProcessReceive};
+use crate::state::{history::HistoryError, user::AccessLevel, App};
+use serde::{Deserialize, Serialize};
+use std::{
+    net::SocketAddr,
+    sync::{Arc, RwLock},
+};
+use uuid::Uuid;
+
+/// Undoes the last change the sender made on their canvas.
+#[derive(Deserialize)]
+pub struct ReceiveUndo {}
+impl ProcessReceive for ReceiveUndo {
+    fn process(
+        self,
+        app: Arc<RwLock<App>>,
+        room_name: &str,
+        addr: SocketAddr,
+    ) -> Result<AnnounceTo, ErrorType> {
+        process(app, room_name, addr, false)
+    }
+}
+
+/// Redoes the last change the sender undid on their canvas.
+#[derive(Deserialize)]
+pub struct ReceiveRedo {}
+impl ProcessReceive for ReceiveRedo {
+    fn process(
+        self,
+        app: Arc<RwLock<App>>,
+        room_name: &str,
+        addr: SocketAddr,
+    ) -> Result<AnnounceTo, ErrorType> {
+        process(app, room_name, addr, true)
+    }
+}
+
+fn process(
+    app: Arc<RwLock<App>>,
+    room_name: &str,
+    addr: SocketAddr,
+    redo: bool,
+) -> Result<AnnounceTo, ErrorType> {
+    let mut app_write_lock = app.write().unwrap();
+    let Some(room) = app_write_lock.get_room_mut(room_name) else {
+        return Error::RoomDoesNotExist.into();
+    };
+    let Some(user) = room.get_user_from_addr(addr) else {
+        // not sure when this would happen but dont feel comfortable
+        // with an unwrap
+        return Error::RoomDoesNotExist.into();
+    };
+    if user.access_level == AccessLevel::View {
+        return Error::NoPermission.into();
+    }
+    let user_uuid = user.uuid;
+    let canvas_id = user.canvas;
+
+    let canvas = room.get_or_create_canvas(canvas_id);
+    let applied = if redo {
+        canvas.redo(user_uuid)
+    } else {
+        canvas.undo(user_uuid)
+    };
+    match applied {
+        Ok(applied) => Ok(AnnounceType::ElementsChanged {
+            elements: applied.elements,
+            deleted_elements: applied.deleted_elements,
+            patched_elements: vec![],
+            threads: applied.threads,
+        }
+        .announce_to_canvas(canvas_id)),
+        Err(e) => Error::from(e).into(),
+    }
+}
+
+#[derive(Serialize)]
+#[serde(tag = "code", rename_all = "snake_case")]
+pub enum Error {
+    NoPermission,
+    RoomDoesNotExist,
+    NothingToUndo,
+    NothingToRedo,
+    /// Someone else changed one of the elements since.
+    NotLastEditor {
+        element: Uuid,
+    },
+    /// Another editor has one of the elements that would change selected.
+    ElementSelected {
+        element: Uuid,
+    },
+}
+impl From<HistoryError> for Error {
+    fn from(value: HistoryError) -> Self {
+        match value {
+            HistoryError::NothingToUndo => Error::NothingToUndo,
+            HistoryError::NothingToRedo => Error::NothingToRedo,
+            HistoryError::NotLastEditor { element } => Error::NotLastEditor { element },
+            HistoryError::ElementSelected { element } => Error::ElementSelected { element },
+        }
+    }
+}
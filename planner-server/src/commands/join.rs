@@ -7,8 +7,38 @@ use crate::state::Color;
 /// [`handle_connection`]: crate::handle_connection
 pub struct Receive {
     pub(crate) room_name: String,
+    /// What the room was like when the user was checked for whether they
+    /// can join it.
+    pub(crate) checked: CheckedRoom,
+    /// The password the user gave, in case they need to be checked again.
     pub(crate) password: Option<String>,
     pub(crate) username: String,
     pub(crate) color: Color,
     pub(crate) canvas: Option<u16>,
+    /// The token from a previous session to resume, if any.
+    pub(crate) resume_token: Option<String>,
+}
+
+/// What a room was like when a user was checked for whether they can join it.
+#[derive(Debug)]
+pub enum CheckedRoom {
+    /// The room didn't exist, so the user will create it with this password
+    /// hash.
+    New { password_hash: Option<String> },
+    /// The room existed with this password hash, and the user gave the right
+    /// password if it has one.
+    Existing { password_hash: Option<String> },
+}
+impl CheckedRoom {
+    /// Whether the check still holds for the room as it is now, given its
+    /// password hash, or `None` if it doesn't exist.
+    pub fn still_holds(&self, room_password_hash: Option<Option<String>>) -> bool {
+        match (self, room_password_hash) {
+            (CheckedRoom::New { .. }, None) => true,
+            (CheckedRoom::Existing { password_hash }, Some(room_password_hash)) => {
+                *password_hash == room_password_hash
+            }
+            _ => false,
+        }
+    }
 }
@@ -0,0 +1,136 @@
+use super::{AnnounceTo, ErrorType, ProcessReceive};
+use crate::state::{
+    marker::{
+        Marker, MarkerType, PingKind, LASER_RATE_WINDOW, LASER_TTL, MAX_LASERS_PER_WINDOW,
+        MAX_LASER_POINTS, MAX_PINGS_PER_WINDOW, PING_RATE_WINDOW, PING_TTL,
+    },
+    user::Position,
+    App,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    net::SocketAddr,
+    sync::{Arc, RwLock},
+};
+use uuid::Uuid;
+
+/// Places a ping on the sender's canvas for a few seconds.
+#[derive(Deserialize)]
+pub struct ReceivePing {
+    kind: PingKind,
+    position: Position,
+}
+impl ProcessReceive for ReceivePing {
+    fn process(
+        self,
+        app: Arc<RwLock<App>>,
+        room_name: &str,
+        addr: SocketAddr,
+    ) -> Result<AnnounceTo, ErrorType> {
+        if !is_finite(&self.position) {
+            return Error::InvalidPosition.into();
+        }
+
+        let mut app_write_lock = app.write().unwrap();
+        let Some(room) = app_write_lock.get_room_mut(room_name) else {
+            return Error::RoomDoesNotExist.into();
+        };
+        let Some(user) = room.get_user_from_addr_mut(addr) else {
+            return Error::RoomDoesNotExist.into();
+        };
+        if !user
+            .ping_limit
+            .check(MAX_PINGS_PER_WINDOW, PING_RATE_WINDOW)
+        {
+            return Error::RateLimited.into();
+        }
+
+        let canvas = user.canvas;
+        let marker = Marker {
+            uuid: Uuid::new_v4(),
+            user: user.uuid,
+            ty: MarkerType::Ping {
+                kind: self.kind,
+                position: self.position,
+            },
+            expires_at: 0,
+        };
+        room.add_marker(canvas, PING_TTL, marker);
+
+        Ok(AnnounceTo::None)
+    }
+}
+
+/// Draws part of a laser pointer trail on the sender's canvas. Each part
+/// fades after a moment, so clients send the trail in small pieces as the
+/// pointer moves.
+#[derive(Deserialize)]
+pub struct ReceiveLaser {
+    points: Vec<Position>,
+}
+impl ProcessReceive for ReceiveLaser {
+    fn process(
+        self,
+        app: Arc<RwLock<App>>,
+        room_name: &str,
+        addr: SocketAddr,
+    ) -> Result<AnnounceTo, ErrorType> {
+        if self.points.is_empty() {
+            return Error::NoPoints.into();
+        }
+        if self.points.len() > MAX_LASER_POINTS {
+            return Error::TooManyPoints {
+                max: MAX_LASER_POINTS,
+            }
+            .into();
+        }
+        if !self.points.iter().all(is_finite) {
+            return Error::InvalidPosition.into();
+        }
+
+        let mut app_write_lock = app.write().unwrap();
+        let Some(room) = app_write_lock.get_room_mut(room_name) else {
+            return Error::RoomDoesNotExist.into();
+        };
+        let Some(user) = room.get_user_from_addr_mut(addr) else {
+            return Error::RoomDoesNotExist.into();
+        };
+        if !user
+            .laser_limit
+            .check(MAX_LASERS_PER_WINDOW, LASER_RATE_WINDOW)
+        {
+            return Error::RateLimited.into();
+        }
+
+        let canvas = user.canvas;
+        let marker = Marker {
+            uuid: Uuid::new_v4(),
+            user: user.uuid,
+            ty: MarkerType::Laser {
+                points: self.points,
+            },
+            expires_at: 0,
+        };
+        room.add_marker(canvas, LASER_TTL, marker);
+
+        Ok(AnnounceTo::None)
+    }
+}
+
+fn is_finite(position: &Position) -> bool {
+    position.x.is_finite() && position.y.is_finite()
+}
+
+#[derive(Serialize)]
+#[serde(tag = "code", rename_all = "snake_case")]
+pub enum Error {
+    RoomDoesNotExist,
+    /// The sender placed too many pings or laser trails recently.
+    RateLimited,
+    /// A position wasn't finite.
+    InvalidPosition,
+    NoPoints,
+    TooManyPoints {
+        max: usize,
+    },
+}
@@ -1,13 +1,30 @@
-use crate::state::{element::Element, user::User, App};
+use crate::state::{
+    chat::ChatMessage,
+    comment::Thread,
+    element::{Element, ElementPatch},
+    marker::Marker,
+    user::{User, UserPresence},
+    App, PublicRoomConfig, RoomCanvas,
+};
 use serde::{Deserialize, Serialize};
 use std::{
+    collections::HashMap,
     net::SocketAddr,
     sync::{Arc, RwLock},
 };
+use tracing::warn;
 use uuid::Uuid;
 
+pub mod chat;
+pub mod comment;
 pub mod elements;
+pub mod history;
 pub mod join;
+pub mod marker;
+pub mod presence;
+pub mod presentation;
+pub mod replay;
+pub mod room;
 pub mod selection;
 pub mod user;
 
@@ -21,25 +38,109 @@ pub struct ReceiveData {
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum ReceiveType {
     AccessLevelAdjustment(user::ReceiveAccessLevelAdjustment),
+    Kick(user::ReceiveKick),
+    Ban(user::ReceiveBan),
+    Unban(user::ReceiveUnban),
     Selection(selection::Receive),
     Canvas(user::ReceiveCanvas),
     ChangedElements(elements::Receive),
+    PatchElements(elements::ReceivePatch),
+    CloseRoom(room::ReceiveCloseRoom),
+    ExportRoom(room::ReceiveExportRoom),
+    ImportRoom(room::ReceiveImportRoom),
+    UpdateRoomConfig(room::ReceiveUpdateRoomConfig),
+    Undo(history::ReceiveUndo),
+    Redo(history::ReceiveRedo),
+    Replay(replay::Receive),
+    Presence(presence::Receive),
+    StartPresentation(presentation::ReceiveStart),
+    StopPresentation(presentation::ReceiveStop),
+    Follow(presentation::ReceiveFollow),
+    Chat(chat::Receive),
+    DeleteChatMessage(chat::ReceiveDelete),
+    CreateThread(comment::ReceiveCreateThread),
+    ReplyToThread(comment::ReceiveReply),
+    ResolveThread(comment::ReceiveResolve),
+    Ping(marker::ReceivePing),
+    Laser(marker::ReceiveLaser),
 }
 
 impl ReceiveData {
+    /// Parses a message from a user.
+    ///
+    /// If it isn't a valid command, they are sent a
+    /// [`MessageError::MalformedMessage`] saying why, along with the message's
+    /// `id` if one could be found in it.
+    pub fn parse(
+        text: &str,
+        app: &Arc<RwLock<App>>,
+        room_name: &str,
+        addr: SocketAddr,
+    ) -> Option<ReceiveData> {
+        let e = match serde_json::from_str::<ReceiveData>(text) {
+            Ok(receive) => return Some(receive),
+            Err(e) => e,
+        };
+        warn!("Couldn't parse message from {addr} as `Receive`: {text}\n\n{e:#?}");
+
+        let id = serde_json::from_str::<serde_json::Value>(text)
+            .ok()
+            .and_then(|msg| msg.get("id")?.as_str()?.parse().ok());
+        respond_malformed(app, room_name, addr, id, e.to_string());
+        None
+    }
+
     pub fn process(self, app: Arc<RwLock<App>>, room_name: &str, addr: SocketAddr) {
         let response = match self.receive {
             ReceiveType::AccessLevelAdjustment(r) => r.process(app.clone(), room_name, addr),
+            ReceiveType::Kick(r) => r.process(app.clone(), room_name, addr),
+            ReceiveType::Ban(r) => r.process(app.clone(), room_name, addr),
+            ReceiveType::Unban(r) => r.process(app.clone(), room_name, addr),
             ReceiveType::Selection(r) => r.process(app.clone(), room_name, addr),
             ReceiveType::Canvas(r) => r.process(app.clone(), room_name, addr),
             ReceiveType::ChangedElements(r) => r.process(app.clone(), room_name, addr),
+            ReceiveType::PatchElements(r) => r.process(app.clone(), room_name, addr),
+            ReceiveType::CloseRoom(r) => r.process(app.clone(), room_name, addr),
+            ReceiveType::ExportRoom(r) => r.process(app.clone(), room_name, addr),
+            ReceiveType::ImportRoom(r) => r.process(app.clone(), room_name, addr),
+            ReceiveType::UpdateRoomConfig(r) => r.process(app.clone(), room_name, addr),
+            ReceiveType::Undo(r) => r.process(app.clone(), room_name, addr),
+            ReceiveType::Redo(r) => r.process(app.clone(), room_name, addr),
+            ReceiveType::Replay(r) => r.process(app.clone(), room_name, addr),
+            ReceiveType::Presence(r) => r.process(app.clone(), room_name, addr),
+            ReceiveType::StartPresentation(r) => r.process(app.clone(), room_name, addr),
+            ReceiveType::StopPresentation(r) => r.process(app.clone(), room_name, addr),
+            ReceiveType::Follow(r) => r.process(app.clone(), room_name, addr),
+            ReceiveType::Chat(r) => r.process(app.clone(), room_name, addr),
+            ReceiveType::DeleteChatMessage(r) => r.process(app.clone(), room_name, addr),
+            ReceiveType::CreateThread(r) => r.process(app.clone(), room_name, addr),
+            ReceiveType::ReplyToThread(r) => r.process(app.clone(), room_name, addr),
+            ReceiveType::ResolveThread(r) => r.process(app.clone(), room_name, addr),
+            ReceiveType::Ping(r) => r.process(app.clone(), room_name, addr),
+            ReceiveType::Laser(r) => r.process(app.clone(), room_name, addr),
         };
-        app.read()
-            .unwrap()
-            .get_room(room_name)
-            .inspect(|x| x.announce(response, addr, self.id));
+        if let Some(room) = app.write().unwrap().get_room_mut(room_name) {
+            room.announce(response, addr, self.id);
+        }
+    }
+}
+/// Tells a user a message they sent couldn't be understood.
+pub fn respond_malformed(
+    app: &Arc<RwLock<App>>,
+    room_name: &str,
+    addr: SocketAddr,
+    id: Option<Uuid>,
+    reason: String,
+) {
+    if let Some(room) = app.read().unwrap().get_room(room_name) {
+        room.respond_error(
+            addr,
+            ErrorType::Message(MessageError::MalformedMessage { reason }),
+            id,
+        );
     }
 }
+
 trait ProcessReceive {
     fn process(
         self,
@@ -90,6 +191,19 @@ pub enum AnnounceType {
         users: Vec<User>,
         /// A list of all elements on the current mapmode.
         elements: Vec<Element>,
+        /// The comment threads on the current mapmode.
+        threads: Vec<Thread>,
+        /// A secret that can be given as the `resume_token` when reconnecting
+        /// to carry on as the same user. A new one is given each time.
+        resume_token: String,
+        /// The room's current settings.
+        config: PublicRoomConfig,
+        /// Where everyone else on the same canvas is looking.
+        presences: Vec<UserPresence>,
+        /// The user presenting, if anyone is.
+        presenter: Option<Uuid>,
+        /// The most recent chat messages, oldest first.
+        chat: Vec<ChatMessage>,
     },
     /// Announces that a user disconnected from the server.
     /// This could be from intentionally leaving the server or from connection issues.
@@ -113,10 +227,96 @@ pub enum AnnounceType {
     CanvasResponse {
         canvas: u16,
         elements: Vec<Element>,
+        threads: Vec<Thread>,
     },
     ElementsChanged {
         elements: Vec<Element>,
         deleted_elements: Vec<Uuid>,
+        /// Changes to only some fields of elements. These should be applied
+        /// to the elements already known about.
+        patched_elements: Vec<ElementPatch>,
+        /// Comment threads that were archived or restored because their
+        /// element was deleted or brought back.
+        threads: Vec<Thread>,
+    },
+    /// Announces that a comment thread was started, replied to, or resolved.
+    ThreadChanged {
+        thread: Thread,
+    },
+    /// Announces that the admin closed the room. Everyone is disconnected
+    /// straight after this and all of the room's data is deleted, so this is
+    /// the last chance to save anything.
+    RoomClosed {
+        closed_by: Uuid,
+    },
+    /// Announces where users on a canvas are looking. Only users whose
+    /// presence changed are included. These aren't given a sequence number
+    /// and can't be replayed.
+    Presence {
+        presences: Vec<UserPresence>,
+    },
+    /// Announces a ping or part of a laser trail. These aren't given a
+    /// sequence number and can't be replayed.
+    MarkerAdded {
+        marker: Marker,
+    },
+    /// Announces that pings or laser trails expired and should be removed.
+    /// These aren't given a sequence number and can't be replayed.
+    MarkersExpired {
+        markers: Vec<Uuid>,
+    },
+    /// Announces that a presentation started. Everyone but the presenter is
+    /// now following them, and anyone on another canvas is moved to the
+    /// presenter's.
+    PresentationStarted {
+        presenter: Uuid,
+    },
+    /// Announces that the presentation ended. Nobody is following anyone
+    /// anymore.
+    PresentationStopped {
+        presenter: Uuid,
+    },
+    /// Announces a new chat message.
+    Chat {
+        message: ChatMessage,
+    },
+    /// Announces that the admin deleted a chat message.
+    ChatMessageDeleted {
+        message: Uuid,
+        deleted_by: Uuid,
+    },
+    /// Announces that the admin changed the room's settings.
+    RoomConfigChanged {
+        config: PublicRoomConfig,
+        changed_by: Uuid,
+    },
+    /// Responds with every canvas in the room, so it can be saved and
+    /// imported into a room later.
+    RoomExport {
+        /// The snapshot version the canvases are in. Imports from older
+        /// versions are migrated to the current one.
+        version: u16,
+        canvases: HashMap<u16, RoomCanvas>,
+    },
+    /// Responds after replaying the announcements the user missed.
+    ReplayFinished {
+        /// How many announcements were replayed.
+        replayed: usize,
+    },
+    /// Responds when the announcements the user missed are too old to
+    /// replay. Everything the user knows about the room should be replaced
+    /// with this.
+    ResyncRequired {
+        users: Vec<User>,
+        /// All elements on the user's current canvas.
+        elements: Vec<Element>,
+        threads: Vec<Thread>,
+        /// The room's current settings.
+        config: PublicRoomConfig,
+        /// The user presenting, if anyone is.
+        presenter: Option<Uuid>,
+        /// The most recent chat messages, oldest first.
+        chat: Vec<ChatMessage>,
     },
 }
 impl AnnounceType {
@@ -136,12 +336,29 @@ impl AnnounceType {
     }
 }
 
+/// Problems with a message itself, rather than with what it asked for.
+#[derive(Serialize)]
+#[serde(tag = "code", rename_all = "snake_case")]
+pub enum MessageError {
+    /// The message wasn't a valid command.
+    MalformedMessage { reason: String },
+}
+
 #[derive(Serialize)]
 #[serde(untagged)]
 pub enum ErrorType {
+    Message(MessageError),
     Selection(selection::Error),
     UserChange(user::UserChangeError),
     ChangedElements(elements::Error),
+    Room(room::Error),
+    History(history::Error),
+    Replay(replay::Error),
+    Presence(presence::Error),
+    Presentation(presentation::Error),
+    Chat(chat::Error),
+    Comment(comment::Error),
+    Marker(marker::Error),
 }
 impl<T> From<elements::Error> for Result<T, ErrorType> {
     fn from(value: elements::Error) -> Self {
@@ -153,6 +370,46 @@ impl<T> From<selection::Error> for Result<T, ErrorType> {
         Err(ErrorType::Selection(value))
     }
 }
+impl<T> From<history::Error> for Result<T, ErrorType> {
+    fn from(value: history::Error) -> Self {
+        Err(ErrorType::History(value))
+    }
+}
+impl<T> From<replay::Error> for Result<T, ErrorType> {
+    fn from(value: replay::Error) -> Self {
+        Err(ErrorType::Replay(value))
+    }
+}
+impl<T> From<presence::Error> for Result<T, ErrorType> {
+    fn from(value: presence::Error) -> Self {
+        Err(ErrorType::Presence(value))
+    }
+}
+impl<T> From<presentation::Error> for Result<T, ErrorType> {
+    fn from(value: presentation::Error) -> Self {
+        Err(ErrorType::Presentation(value))
+    }
+}
+impl<T> From<chat::Error> for Result<T, ErrorType> {
+    fn from(value: chat::Error) -> Self {
+        Err(ErrorType::Chat(value))
+    }
+}
+impl<T> From<comment::Error> for Result<T, ErrorType> {
+    fn from(value: comment::Error) -> Self {
+        Err(ErrorType::Comment(value))
+    }
+}
+impl<T> From<marker::Error> for Result<T, ErrorType> {
+    fn from(value: marker::Error) -> Self {
+        Err(ErrorType::Marker(value))
+    }
+}
+impl<T> From<room::Error> for Result<T, ErrorType> {
+    fn from(value: room::Error) -> Self {
+        Err(ErrorType::Room(value))
+    }
+}
 impl<T> From<user::UserChangeError> for Result<T, ErrorType> {
     fn from(value: user::UserChangeError) -> Self {
         Err(ErrorType::UserChange(value))
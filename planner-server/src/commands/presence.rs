@@ -0,0 +1,47 @@
+use super::{AnnounceTo, ErrorType, ProcessReceive};
+use crate::state::{user::Presence, App};
+use serde::{Deserialize, Serialize};
+use std::{
+    net::SocketAddr,
+    sync::{Arc, RwLock},
+};
+
+/// Updates where the sender is looking on their canvas.
+///
+/// This isn't announced straight away. Everyone's latest presence is sent at
+/// a fixed rate instead, so clients can send this as often as they like.
+#[derive(Deserialize)]
+pub struct Receive {
+    #[serde(flatten)]
+    presence: Presence,
+}
+impl ProcessReceive for Receive {
+    fn process(
+        self,
+        app: Arc<RwLock<App>>,
+        room_name: &str,
+        addr: SocketAddr,
+    ) -> Result<AnnounceTo, ErrorType> {
+        if !self.presence.is_valid() {
+            return Error::InvalidPresence.into();
+        }
+
+        let mut app_write_lock = app.write().unwrap();
+        let Some(room) = app_write_lock.get_room_mut(room_name) else {
+            return Error::RoomDoesNotExist.into();
+        };
+        if room.set_presence(addr, self.presence).is_none() {
+            return Error::RoomDoesNotExist.into();
+        }
+
+        Ok(AnnounceTo::None)
+    }
+}
+
+#[derive(Serialize)]
+#[serde(tag = "code", rename_all = "snake_case")]
+pub enum Error {
+    RoomDoesNotExist,
+    /// A number wasn't finite, or the zoom wasn't positive.
+    InvalidPresence,
+}
@@ -0,0 +1,118 @@
+use super::{AnnounceTo, AnnounceType, ErrorType, ProcessReceive};
+use crate::state::{user::AccessLevel, App};
+use serde::{Deserialize, Serialize};
+use std::{
+    net::SocketAddr,
+    sync::{Arc, RwLock},
+};
+
+/// Starts presenting to everyone else in the room. They follow the sender to
+/// whichever canvas they switch to, and can see where they're looking from
+/// their presence.
+#[derive(Deserialize)]
+pub struct ReceiveStart {}
+impl ProcessReceive for ReceiveStart {
+    fn process(
+        self,
+        app: Arc<RwLock<App>>,
+        room_name: &str,
+        addr: SocketAddr,
+    ) -> Result<AnnounceTo, ErrorType> {
+        let mut app_write_lock = app.write().unwrap();
+        let Some(room) = app_write_lock.get_room_mut(room_name) else {
+            return Error::RoomDoesNotExist.into();
+        };
+        let Some(user) = room.get_user_from_addr(addr) else {
+            return Error::RoomDoesNotExist.into();
+        };
+        // Only the admin can make everyone follow them.
+        if user.access_level != AccessLevel::Admin {
+            return Error::NoPermission.into();
+        }
+
+        let presenter = user.uuid;
+        room.start_presentation(presenter);
+        Ok(AnnounceTo::None)
+    }
+}
+
+/// Stops the presentation. Only the admin or the presenter can do this.
+#[derive(Deserialize)]
+pub struct ReceiveStop {}
+impl ProcessReceive for ReceiveStop {
+    fn process(
+        self,
+        app: Arc<RwLock<App>>,
+        room_name: &str,
+        addr: SocketAddr,
+    ) -> Result<AnnounceTo, ErrorType> {
+        let mut app_write_lock = app.write().unwrap();
+        let Some(room) = app_write_lock.get_room_mut(room_name) else {
+            return Error::RoomDoesNotExist.into();
+        };
+        let Some(user) = room.get_user_from_addr(addr) else {
+            return Error::RoomDoesNotExist.into();
+        };
+        let is_presenter = room.get_presenter().is_some_and(|p| p.uuid == user.uuid);
+        if user.access_level != AccessLevel::Admin && !is_presenter {
+            return Error::NoPermission.into();
+        }
+
+        if !room.stop_presentation() {
+            return Error::NotPresenting.into();
+        }
+        Ok(AnnounceTo::None)
+    }
+}
+
+/// Stops or starts following the presenter. Following moves the sender to
+/// the presenter's canvas.
+#[derive(Deserialize)]
+pub struct ReceiveFollow {
+    following: bool,
+}
+impl ProcessReceive for ReceiveFollow {
+    fn process(
+        self,
+        app: Arc<RwLock<App>>,
+        room_name: &str,
+        addr: SocketAddr,
+    ) -> Result<AnnounceTo, ErrorType> {
+        let mut app_write_lock = app.write().unwrap();
+        let Some(room) = app_write_lock.get_room_mut(room_name) else {
+            return Error::RoomDoesNotExist.into();
+        };
+        let Some(presenter) = room.get_presenter() else {
+            return Error::NotPresenting.into();
+        };
+        let presenter_canvas = presenter.canvas;
+        let presenter = presenter.uuid;
+        let Some(user) = room.get_user_from_addr_mut(addr) else {
+            return Error::RoomDoesNotExist.into();
+        };
+        if user.uuid == presenter {
+            return Error::IsPresenter.into();
+        }
+
+        user.following = self.following;
+        let uuid = user.uuid;
+        if self.following && user.canvas != presenter_canvas {
+            room.move_to_canvas(uuid, presenter_canvas);
+        }
+
+        // Canvas was switched in `move_to_canvas`, if it needed to be.
+        let user = room.get_user(uuid).unwrap().clone();
+        Ok(AnnounceType::UserChange { user: user.into() }.announce_to_all())
+    }
+}
+
+#[derive(Serialize)]
+#[serde(tag = "code", rename_all = "snake_case")]
+pub enum Error {
+    RoomDoesNotExist,
+    NoPermission,
+    /// Nobody is presenting.
+    NotPresenting,
+    /// The presenter can't follow themselves.
+    IsPresenter,
+}
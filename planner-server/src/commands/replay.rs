@@ -0,0 +1,70 @@
+use super::{AnnounceTo, AnnounceType, ErrorType, ProcessReceive};
+use crate::state::App;
+use serde::{Deserialize, Serialize};
+use std::{
+    net::SocketAddr,
+    sync::{Arc, RwLock},
+};
+
+/// Replays every announcement the sender would have been sent after a
+/// sequence number, such as after reconnecting.
+///
+/// The announcements are sent exactly as they were the first time, followed
+/// by a [`AnnounceType::ReplayFinished`]. If some of them are too old to
+/// replay, a [`AnnounceType::ResyncRequired`] is sent instead.
+#[derive(Deserialize)]
+pub struct Receive {
+    /// The sequence number of the last announcement the sender received.
+    after: u64,
+}
+impl ProcessReceive for Receive {
+    fn process(
+        self,
+        app: Arc<RwLock<App>>,
+        room_name: &str,
+        addr: SocketAddr,
+    ) -> Result<AnnounceTo, ErrorType> {
+        let app_read_lock = app.read().unwrap();
+        let Some(room) = app_read_lock.get_room(room_name) else {
+            return Error::RoomDoesNotExist.into();
+        };
+        let Some(user) = room.get_user_from_addr(addr) else {
+            // not sure when this would happen but dont feel comfortable
+            // with an unwrap
+            return Error::RoomDoesNotExist.into();
+        };
+
+        match room.events_after(self.after, user.canvas) {
+            Some(events) => {
+                for msg in &events {
+                    user.send_str(msg);
+                }
+                Ok(AnnounceType::ReplayFinished {
+                    replayed: events.len(),
+                }
+                .respond_to_sender())
+            }
+            None => Ok(AnnounceType::ResyncRequired {
+                users: room.get_users().map(|u| u.clone().into()).collect(),
+                elements: room
+                    .get_canvas(user.canvas)
+                    .map(|canvas| canvas.elements.clone())
+                    .unwrap_or_default(),
+                threads: room
+                    .get_canvas(user.canvas)
+                    .map(|canvas| canvas.threads.clone())
+                    .unwrap_or_default(),
+                config: room.get_config().public(),
+                presenter: room.get_presenter().map(|presenter| presenter.uuid),
+                chat: room.get_chat().iter().cloned().collect(),
+            }
+            .respond_to_sender()),
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(tag = "code", rename_all = "snake_case")]
+pub enum Error {
+    RoomDoesNotExist,
+}
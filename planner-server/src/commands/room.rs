@@ -0,0 +1,245 @@
+use std::{
+    net::SocketAddr,
+    sync::{Arc, RwLock},
+};
+
+use crate::state::{element::ValidationError, password, store::snapshot, user::AccessLevel, App};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::{AnnounceTo, AnnounceType, ErrorType, ProcessReceive};
+
+/// Closes the room, disconnecting everyone in it and deleting all of its
+/// data. This frees up the room's name.
+#[derive(Deserialize)]
+pub struct ReceiveCloseRoom {}
+impl ProcessReceive for ReceiveCloseRoom {
+    fn process(
+        self,
+        app: Arc<RwLock<App>>,
+        room_name: &str,
+        addr: SocketAddr,
+    ) -> Result<AnnounceTo, ErrorType> {
+        let mut app_write_lock = app.write().unwrap();
+        let Some(room) = app_write_lock.get_room_mut(room_name) else {
+            return Error::RoomDoesNotExist.into();
+        };
+        let Some(user) = room.get_user_from_addr(addr) else {
+            // not sure when this would happen but dont feel comfortable
+            // with an unwrap
+            return Error::RoomDoesNotExist.into();
+        };
+        // Only the admin can close the room.
+        if user.access_level != AccessLevel::Admin {
+            return Error::NoPermission.into();
+        }
+
+        // Let everyone know first so they have a chance to save anything
+        // before they get disconnected.
+        let closed_by = user.uuid;
+        room.announce_to_all(AnnounceType::RoomClosed { closed_by }, None);
+        app_write_lock.close_room(room_name);
+
+        // Nobody is left to respond to.
+        Ok(AnnounceTo::None)
+    }
+}
+
+/// Exports every canvas in the room as a single document.
+#[derive(Deserialize)]
+pub struct ReceiveExportRoom {}
+impl ProcessReceive for ReceiveExportRoom {
+    fn process(
+        self,
+        app: Arc<RwLock<App>>,
+        room_name: &str,
+        addr: SocketAddr,
+    ) -> Result<AnnounceTo, ErrorType> {
+        let app_read_lock = app.read().unwrap();
+        let Some(room) = app_read_lock.get_room(room_name) else {
+            return Error::RoomDoesNotExist.into();
+        };
+        let Some(user) = room.get_user_from_addr(addr) else {
+            return Error::RoomDoesNotExist.into();
+        };
+        if !room.get_config().can_export(user.access_level) {
+            return Error::NoPermission.into();
+        }
+
+        Ok(AnnounceType::RoomExport {
+            version: snapshot::SNAPSHOT_VERSION,
+            canvases: room.export_canvases(),
+        }
+        .respond_to_sender())
+    }
+}
+
+/// Replaces every canvas in the room with ones from a document created by
+/// [`ReceiveExportRoom`].
+///
+/// Comment threads in the document aren't imported, and its elements count as
+/// created by the admin. Who wrote or made them can't be checked, so they
+/// could be used to put words in other users' mouths.
+#[derive(Deserialize)]
+pub struct ReceiveImportRoom {
+    version: u16,
+    canvases: serde_json::Value,
+}
+impl ProcessReceive for ReceiveImportRoom {
+    fn process(
+        self,
+        app: Arc<RwLock<App>>,
+        room_name: &str,
+        addr: SocketAddr,
+    ) -> Result<AnnounceTo, ErrorType> {
+        let mut app_write_lock = app.write().unwrap();
+        let Some(room) = app_write_lock.get_room_mut(room_name) else {
+            return Error::RoomDoesNotExist.into();
+        };
+        let Some(user) = room.get_user_from_addr(addr) else {
+            return Error::RoomDoesNotExist.into();
+        };
+        // Only the admin can overwrite everything in the room.
+        if user.access_level != AccessLevel::Admin {
+            return Error::NoPermission.into();
+        }
+        let user_uuid = user.uuid;
+
+        let mut canvases = match snapshot::migrate_canvases(self.version, self.canvases) {
+            Ok(canvases) => canvases,
+            Err(e) => {
+                return Error::InvalidDocument {
+                    reason: e.to_string(),
+                }
+                .into()
+            }
+        };
+        for el in canvases.values().flat_map(|canvas| &canvas.elements) {
+            if let Err(error) = el.validate() {
+                return Error::InvalidElement {
+                    element: el.uuid,
+                    error,
+                }
+                .into();
+            }
+        }
+        // Who made the elements can't be checked either, so they count as
+        // made by the admin who imported them.
+        for canvas in canvases.values_mut() {
+            canvas.threads.clear();
+            for el in &mut canvas.elements {
+                el.mark_created(user_uuid);
+            }
+        }
+        room.replace_canvases(canvases);
+
+        Ok(AnnounceTo::None)
+    }
+}
+
+/// Changes the room's settings. Settings that aren't given are left as they
+/// are.
+#[derive(Deserialize)]
+pub struct ReceiveUpdateRoomConfig {
+    new_users_default_editor: Option<bool>,
+    password: Option<PasswordChange>,
+    export_admin_only: Option<bool>,
+    view_only_chat: Option<bool>,
+}
+#[derive(Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+enum PasswordChange {
+    /// Sets a new password, or changes the existing one. Users already in the
+    /// room don't need to enter it.
+    Set { password: String },
+    /// Lets anyone join the room without a password.
+    Remove,
+}
+impl ProcessReceive for ReceiveUpdateRoomConfig {
+    fn process(
+        self,
+        app: Arc<RwLock<App>>,
+        room_name: &str,
+        addr: SocketAddr,
+    ) -> Result<AnnounceTo, ErrorType> {
+        let mut app_write_lock = app.write().unwrap();
+        let Some(room) = app_write_lock.get_room_mut(room_name) else {
+            return Error::RoomDoesNotExist.into();
+        };
+        let Some(user) = room.get_user_from_addr(addr) else {
+            return Error::RoomDoesNotExist.into();
+        };
+        // Only the admin can change the room's settings.
+        if user.access_level != AccessLevel::Admin {
+            return Error::NoPermission.into();
+        }
+        let changed_by = user.uuid;
+        drop(app_write_lock);
+
+        // Hashing is slow, so do it without holding the lock, and let the
+        // runtime move other connections off this thread in the meantime.
+        let password_hash = match &self.password {
+            // An empty password can't be entered when joining, so it would
+            // lock everyone out.
+            Some(PasswordChange::Set { password }) if password.is_empty() => {
+                return Error::EmptyPassword.into();
+            }
+            Some(PasswordChange::Set { password }) => {
+                Some(tokio::task::block_in_place(|| password::hash(password)))
+            }
+            _ => None,
+        };
+
+        let mut app_write_lock = app.write().unwrap();
+        let Some(room) = app_write_lock.get_room_mut(room_name) else {
+            return Error::RoomDoesNotExist.into();
+        };
+        // Someone else may have been made the admin in the meantime.
+        if room
+            .get_user_from_addr(addr)
+            .is_none_or(|user| user.access_level != AccessLevel::Admin)
+        {
+            return Error::NoPermission.into();
+        }
+        let config = room.get_config_mut();
+        if let Some(new_users_default_editor) = self.new_users_default_editor {
+            config.new_users_default_editor = new_users_default_editor;
+        }
+        match self.password {
+            Some(PasswordChange::Set { .. }) => config.password_hash = password_hash,
+            Some(PasswordChange::Remove) => config.password_hash = None,
+            None => {}
+        }
+        if let Some(export_admin_only) = self.export_admin_only {
+            config.export_admin_only = export_admin_only;
+        }
+        if let Some(view_only_chat) = self.view_only_chat {
+            config.view_only_chat = view_only_chat;
+        }
+
+        Ok(AnnounceType::RoomConfigChanged {
+            config: config.public(),
+            changed_by,
+        }
+        .announce_to_all())
+    }
+}
+
+#[derive(Serialize)]
+#[serde(tag = "code", rename_all = "snake_case")]
+pub enum Error {
+    RoomDoesNotExist,
+    NoPermission,
+    /// The room's password can't be set to an empty string.
+    EmptyPassword,
+    /// The imported document couldn't be read.
+    InvalidDocument {
+        reason: String,
+    },
+    /// An element in the imported document had an invalid field.
+    InvalidElement {
+        element: Uuid,
+        #[serde(flatten)]
+        error: ValidationError,
+    },
+}
@@ -3,7 +3,10 @@ use std::{
     sync::{Arc, RwLock},
 };
 
-use crate::state::{user::AccessLevel, App};
+use crate::state::{
+    user::{AccessLevel, DisconnectReason},
+    App,
+};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
@@ -43,12 +46,118 @@ impl ProcessReceive for ReceiveAccessLevelAdjustment {
         Ok(AnnounceTo::None)
     }
 }
+/// Removes a user from the room. They can rejoin straight away.
+#[derive(Deserialize)]
+pub struct ReceiveKick {
+    user: Uuid,
+}
+impl ProcessReceive for ReceiveKick {
+    fn process(
+        self,
+        app: Arc<RwLock<App>>,
+        room_name: &str,
+        addr: SocketAddr,
+    ) -> Result<AnnounceTo, ErrorType> {
+        let mut app_write_lock = app.write().unwrap();
+        let Some(room) = app_write_lock.get_room_mut(room_name) else {
+            return UserChangeError::RoomDoesNotExist.into();
+        };
+        let Some(user) = room.get_user_from_addr(addr) else {
+            return UserChangeError::RoomDoesNotExist.into();
+        };
+        if user.access_level != AccessLevel::Admin {
+            return UserChangeError::NoPermission.into();
+        }
+        if user.uuid == self.user {
+            return UserChangeError::CannotTargetSelf.into();
+        }
+
+        let by = user.uuid;
+        if !room.kick_user(self.user, DisconnectReason::Kicked { by }) {
+            return UserChangeError::UserDoesNotExist.into();
+        }
+        Ok(AnnounceTo::None)
+    }
+}
+
+/// Removes a user from the room and stops them from rejoining it.
+#[derive(Deserialize)]
+pub struct ReceiveBan {
+    user: Uuid,
+    /// Whether to ban the IP address the user is connected from, which is the
+    /// default. If `false`, only their session is banned, so they can rejoin
+    /// straight away as someone new.
+    ip: Option<bool>,
+}
+impl ProcessReceive for ReceiveBan {
+    fn process(
+        self,
+        app: Arc<RwLock<App>>,
+        room_name: &str,
+        addr: SocketAddr,
+    ) -> Result<AnnounceTo, ErrorType> {
+        let mut app_write_lock = app.write().unwrap();
+        let Some(room) = app_write_lock.get_room_mut(room_name) else {
+            return UserChangeError::RoomDoesNotExist.into();
+        };
+        let Some(user) = room.get_user_from_addr(addr) else {
+            return UserChangeError::RoomDoesNotExist.into();
+        };
+        if user.access_level != AccessLevel::Admin {
+            return UserChangeError::NoPermission.into();
+        }
+        if user.uuid == self.user {
+            return UserChangeError::CannotTargetSelf.into();
+        }
+
+        let by = user.uuid;
+        if !room.ban_user(self.user, self.ip.unwrap_or(true), by) {
+            return UserChangeError::UserDoesNotExist.into();
+        }
+        Ok(AnnounceTo::None)
+    }
+}
+
+/// Lets a banned user join the room again.
+#[derive(Deserialize)]
+pub struct ReceiveUnban {
+    /// The UUID the user had when they were banned.
+    user: Uuid,
+}
+impl ProcessReceive for ReceiveUnban {
+    fn process(
+        self,
+        app: Arc<RwLock<App>>,
+        room_name: &str,
+        addr: SocketAddr,
+    ) -> Result<AnnounceTo, ErrorType> {
+        let mut app_write_lock = app.write().unwrap();
+        let Some(room) = app_write_lock.get_room_mut(room_name) else {
+            return UserChangeError::RoomDoesNotExist.into();
+        };
+        let Some(user) = room.get_user_from_addr(addr) else {
+            return UserChangeError::RoomDoesNotExist.into();
+        };
+        if user.access_level != AccessLevel::Admin {
+            return UserChangeError::NoPermission.into();
+        }
+
+        if !room.unban_user(self.user) {
+            return UserChangeError::UserNotBanned.into();
+        }
+        Ok(AnnounceTo::None)
+    }
+}
+
 #[derive(Serialize)]
 #[serde(tag = "code", rename_all = "snake_case")]
 pub enum UserChangeError {
     RoomDoesNotExist,
     NoPermission,
     UserDoesNotExist,
+    /// Admins can't kick or ban themselves.
+    CannotTargetSelf,
+    UserNotBanned,
 }
 
 #[derive(Deserialize)]
@@ -62,32 +171,35 @@ impl ProcessReceive for ReceiveCanvas {
         room_name: &str,
         addr: SocketAddr,
     ) -> Result<AnnounceTo, ErrorType> {
-        {
-            let mut app_write_lock = app.write().unwrap();
-            let Some(room) = app_write_lock.get_room_mut(room_name) else {
-                return UserChangeError::RoomDoesNotExist.into();
-            };
-            room.switch_canvas(addr, self.canvas);
+        let mut app_write_lock = app.write().unwrap();
+        let Some(room) = app_write_lock.get_room_mut(room_name) else {
+            return UserChangeError::RoomDoesNotExist.into();
+        };
+        // Switching canvas by yourself means you've stopped following
+        // the presenter.
+        if let Some(user) = room.get_user_from_addr_mut(addr) {
+            user.following = false;
         }
-        let elements;
-        let respond_user;
+        room.switch_canvas(addr, self.canvas);
+        if room
+            .get_presenter()
+            .is_some_and(|presenter| presenter.addr == addr)
         {
-            let app_read_lock = app.read().unwrap();
-            let Some(room) = app_read_lock.get_room(room_name) else {
-                return UserChangeError::RoomDoesNotExist.into();
-            };
-            let Some(user) = room.get_user_from_addr(addr) else {
-                return UserChangeError::RoomDoesNotExist.into();
-            };
-            respond_user = user.clone();
-            // Canvas was created in `switch_canvas`
-            elements = room.get_canvas(self.canvas).unwrap().elements.clone();
+            room.move_followers();
         }
 
+        let canvas = room.get_or_create_canvas(self.canvas);
+        let elements = canvas.elements.clone();
+        let threads = canvas.threads.clone();
+        let Some(respond_user) = room.get_user_from_addr(addr).cloned() else {
+            return UserChangeError::RoomDoesNotExist.into();
+        };
+
         Ok(AnnounceTo::ResponseAndAnnounce {
             respond: AnnounceType::CanvasResponse {
                 canvas: self.canvas,
                 elements,
+                threads,
             },
             announce: AnnounceType::UserChange {
                 user: respond_user.into(),
@@ -5,23 +5,38 @@ use std::{
     collections::HashMap,
     convert::Infallible,
     net::{IpAddr, Ipv4Addr, SocketAddr},
-    sync::{Arc, RwLock},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, RwLock,
+    },
     time::Duration,
 };
 
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
 use clap::Parser;
+use commands::join::CheckedRoom;
 use futures_channel::mpsc::unbounded;
 use futures_util::{future, pin_mut, StreamExt, TryStreamExt};
 use hyper::{
     body::{Bytes, Incoming},
-    header::{self, HeaderValue, CONNECTION, CONTENT_TYPE, SEC_WEBSOCKET_ACCEPT, UPGRADE},
+    header::{
+        self, HeaderName, HeaderValue, CONNECTION, CONTENT_TYPE, SEC_WEBSOCKET_ACCEPT,
+        SEC_WEBSOCKET_PROTOCOL, UPGRADE,
+    },
     server::conn::http1,
     service::service_fn,
     Method, Request, Response, StatusCode, Version,
 };
 use hyper_util::rt::TokioIo;
 use serde::Serialize;
-use state::{user::AccessLevel, App, Color, RoomUser};
+use state::{
+    marker::RateLimit,
+    password,
+    store::{self, FileStore, MemoryStore, RoomStore, SqliteStore},
+    user::{self, AccessLevel},
+    App, Color, RoomUser,
+};
 use tokio::{net::TcpListener, time};
 use tokio_tungstenite::tungstenite::{
     protocol::{frame::coding::CloseCode, CloseFrame},
@@ -35,6 +50,12 @@ pub(crate) mod state;
 
 const PROTOCOL_VERSION: usize = 1;
 
+/// How often users' cursors and viewports are sent to everyone else. Any
+/// sent more often than this are combined.
+const PRESENCE_INTERVAL: Duration = Duration::from_millis(100);
+/// How often expired pings and laser trails are removed.
+const MARKER_SWEEP_INTERVAL: Duration = Duration::from_millis(250);
+
 const MIN_ROOM_NAME_LEN: usize = 3;
 const MAX_ROOM_NAME_LEN: usize = 32;
 const MIN_USERNAME_LEN: usize = 1;
@@ -46,12 +67,46 @@ struct Cli {
     /// The IP address and port to host on.
     #[arg(default_value_t = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 10999))]
     ip: SocketAddr,
+    /// Where rooms are saved to when everyone leaves them.
+    #[arg(long, value_enum, default_value_t = StoreKind::File)]
+    store: StoreKind,
+    /// The directory rooms are saved to when using the `file` or `sqlite`
+    /// store.
+    #[arg(long, default_value = "rooms")]
+    data_dir: PathBuf,
+    /// How many days a room is kept for after the last person leaves it.
+    #[arg(long, default_value_t = 7)]
+    retention_days: u64,
+    /// How many seconds a user whose connection dropped has to reconnect and
+    /// resume their session, before everyone else is told they left.
+    #[arg(long, default_value_t = 30)]
+    resume_grace_secs: u64,
+    /// How many seconds the admin stays the admin after disconnecting, before
+    /// someone else is made the admin.
+    #[arg(long, default_value_t = 120)]
+    admin_grace_secs: u64,
+    /// The header a trusted proxy in front of the server puts each client's
+    /// IP address in, such as `CF-Connecting-IP` or `X-Forwarded-For`.
+    /// Without it, IP bans ban whoever the connection came from, which
+    /// behind a proxy is the proxy itself.
+    #[arg(long)]
+    client_ip_header: Option<HeaderName>,
     /// The debug level to use. Can specify up to 3 times for increasing
     /// log levels.
     #[arg(short, long, action = clap::ArgAction::Count)]
     verbose: u8,
 }
 
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum StoreKind {
+    /// Keep rooms in memory. They are lost when the server stops.
+    Memory,
+    /// Save each room to its own file in the data directory.
+    File,
+    /// Save rooms to an SQLite database in the data directory.
+    Sqlite,
+}
+
 ////////
 // A lot of this code in this file is adapted from Tokio Tungstenite examples
 // https://github.com/snapview/tokio-tungstenite/blob/master/examples/server-custom-accept.rs
@@ -63,46 +118,105 @@ async fn handle_connection(
     user: commands::join::Receive,
     ws_stream: tokio_tungstenite::WebSocketStream<TokioIo<hyper::upgrade::Upgraded>>,
     addr: SocketAddr,
+    ip: IpAddr,
 ) {
     let (tx, rx) = unbounded::<Message>();
-    let (outgoing, incoming) = ws_stream.split();
 
     let room_name = user.room_name;
-    {
-        let mut app = app.write().unwrap();
-        let room = app.get_or_insert_room(room_name.clone(), user.password);
-        let admin = room.get_admin();
-        let new_user = RoomUser {
-            addr,
-            tx,
-            uuid: Uuid::new_v4(),
-            username: user.username,
-            color: user.color,
-            canvas: user
-                .canvas
-                .unwrap_or(admin.map(|admin| admin.canvas).unwrap_or(0)),
-            access_level: match admin {
-                None => AccessLevel::Admin,
-                Some(_) => room.get_config().get_default_access_level(),
-            },
+    let mut checked = user.checked;
+    loop {
+        {
+            let mut app = app.write().unwrap();
+            let room_password_hash = app
+                .get_or_load_room(&room_name)
+                .map(|room| room.password_hash().map(str::to_string));
+            if checked.still_holds(room_password_hash) {
+                let password_hash = match checked {
+                    CheckedRoom::New { password_hash } => password_hash,
+                    CheckedRoom::Existing { .. } => None,
+                };
+                let room = app.get_or_insert_room(room_name.clone(), password_hash);
+                let resumed = user
+                    .resume_token
+                    .as_deref()
+                    .is_some_and(|token| room.resume_user(token, addr, ip, &tx));
+                if !resumed {
+                    let admin = room.get_admin();
+                    // Unless they asked for a specific canvas, people joining
+                    // during a presentation follow the presenter.
+                    let presenter_canvas = room.get_presenter().map(|p| p.canvas);
+                    let following = presenter_canvas.is_some() && user.canvas.is_none();
+                    let new_user = RoomUser {
+                        addr,
+                        ip,
+                        tx,
+                        uuid: Uuid::new_v4(),
+                        username: user.username,
+                        color: user.color,
+                        canvas: user
+                            .canvas
+                            .or(presenter_canvas)
+                            .unwrap_or(admin.map(|admin| admin.canvas).unwrap_or(0)),
+                        access_level: match admin {
+                            None => AccessLevel::Admin,
+                            Some(_) => room.get_config().get_default_access_level(),
+                        },
+                        resume_token: user::new_resume_token(),
+                        disconnected_at: None,
+                        admin_held_until: None,
+                        presence: None,
+                        presence_changed: false,
+                        following,
+                        ping_limit: RateLimit::new(),
+                        laser_limit: RateLimit::new(),
+                    };
+                    room.add_user(new_user);
+                }
+                break;
+            }
+            app.unload_room_if_empty(&room_name);
+        }
+
+        // The room was created, or its password changed, while the user was
+        // being checked, so check them again.
+        checked = match check_can_join(
+            &app,
+            &room_name,
+            user.password.as_deref(),
+            ip,
+            user.resume_token.as_deref(),
+        )
+        .await
+        {
+            Ok(checked) => checked,
+            Err(err) => return err.respond_on_websocket(ws_stream).await,
         };
-        room.add_user(new_user);
-    };
+    }
+    let (outgoing, incoming) = ws_stream.split();
 
+    // Browsers send a close frame when the user leaves the page on purpose,
+    // but not when their connection drops.
+    let closed_by_client = AtomicBool::new(false);
     let broadcast_incoming = incoming.try_for_each(|msg| {
-        if msg.is_text() {
+        if msg.is_close() {
+            closed_by_client.store(true, Ordering::Relaxed);
+        } else if msg.is_text() {
             let text = msg.to_text().unwrap();
             trace!("Received a message from {addr}: {text}");
             // Try parse as 'Receive'
-            let receive = match serde_json::from_str::<commands::ReceiveData>(text) {
-                Ok(t) => t,
-                Err(e) => {
-                    warn!("Couldn't parse message from {addr} as `Receive`: {text}\n\n{e:#?}");
-                    return future::ok(());
-                }
+            let Some(receive) = commands::ReceiveData::parse(text, &app, &room_name, addr) else {
+                return future::ok(());
             };
             // Now process the request
             receive.process(app.clone(), &room_name, addr)
+        } else if msg.is_binary() {
+            commands::respond_malformed(
+                &app,
+                &room_name,
+                addr,
+                None,
+                "commands must be sent as text".to_string(),
+            );
         }
         future::ok(())
     });
@@ -112,7 +226,11 @@ async fn handle_connection(
     future::select(broadcast_incoming, receive_from_others).await;
 
     trace!("{} disconnected", &addr);
-    app.write().unwrap().disconnect_user(&room_name, &addr);
+    if closed_by_client.load(Ordering::Relaxed) {
+        app.write().unwrap().disconnect_user(&room_name, &addr);
+    } else {
+        app.write().unwrap().connection_lost(&room_name, &addr);
+    }
 }
 
 const UPGRADE_HEADER_VALUE: HeaderValue = HeaderValue::from_static("Upgrade");
@@ -172,6 +290,141 @@ fn is_valid_request(req: &Request<Incoming>) -> bool {
     true
 }
 
+/// The prefix of the `Sec-WebSocket-Protocol` entry the room password is sent
+/// in, as `password.<base64url>`.
+const PASSWORD_PROTOCOL_PREFIX: &str = "password.";
+/// The prefix of the `Sec-WebSocket-Protocol` entry the resume token is sent
+/// in, as `resume.<token>`.
+const RESUME_TOKEN_PROTOCOL_PREFIX: &str = "resume.";
+
+/// What was read from the `Sec-WebSocket-Protocol` header.
+#[derive(Default)]
+struct WebSocketProtocols {
+    /// The first subprotocol that isn't a credential, which must be echoed
+    /// back for browsers to accept the connection.
+    protocol: Option<String>,
+    password: Option<String>,
+    resume_token: Option<String>,
+}
+/// Reads the `Sec-WebSocket-Protocol` header.
+///
+/// Browsers don't let websockets set any other headers, so clients send the
+/// room password and resume token as subprotocols rather than in the URL,
+/// where they would end up in logs.
+fn read_websocket_protocols(req: &Request<Incoming>) -> WebSocketProtocols {
+    let mut read = WebSocketProtocols::default();
+    let protocols = req
+        .headers()
+        .get_all(SEC_WEBSOCKET_PROTOCOL)
+        .iter()
+        .filter_map(|h| h.to_str().ok())
+        .flat_map(|h| h.split(','))
+        .map(str::trim);
+    for p in protocols {
+        if let Some(encoded) = p.strip_prefix(PASSWORD_PROTOCOL_PREFIX) {
+            read.password = URL_SAFE_NO_PAD
+                .decode(encoded)
+                .ok()
+                .and_then(|p| String::from_utf8(p).ok());
+        } else if let Some(token) = p.strip_prefix(RESUME_TOKEN_PROTOCOL_PREFIX) {
+            read.resume_token = Some(token.to_string());
+        } else if read.protocol.is_none() && !p.is_empty() {
+            read.protocol = Some(p.to_string());
+        }
+    }
+    read
+}
+
+/// Finds the IP address of the client that sent a request.
+///
+/// If the server is behind a proxy, the proxy's address is the one connecting
+/// to the server, so the client's is read from `client_ip_header` instead.
+/// When a header lists several addresses, like `X-Forwarded-For`, the last
+/// one is used, as that's the one the proxy added.
+fn client_ip(
+    req: &Request<Incoming>,
+    client_ip_header: Option<&HeaderName>,
+    addr: SocketAddr,
+) -> IpAddr {
+    let Some(name) = client_ip_header else {
+        return addr.ip();
+    };
+    let ip = req
+        .headers()
+        .get(name)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.rsplit(',').next())
+        .and_then(|ip| ip.trim().parse().ok());
+    ip.unwrap_or_else(|| {
+        warn!("Request from {addr} had no valid `{name}` header, using its address instead");
+        addr.ip()
+    })
+}
+
+/// Checks whether a user can join a room, returning what the room was like
+/// when they were checked.
+///
+/// Checking the password is slow, so it's done without holding the lock. The
+/// room can change in the meantime, so the result has to be compared with
+/// the room again when the user joins it.
+async fn check_can_join(
+    app: &AppState,
+    room_name: &str,
+    password: Option<&str>,
+    ip: IpAddr,
+    resume_token: Option<&str>,
+) -> Result<CheckedRoom, JoinError> {
+    // The write guard can't be held across an `.await`.
+    let room_password_hash = {
+        let mut app = app.write().unwrap();
+        let mut banned = false;
+        let room_password_hash = app.get_or_load_room(room_name).map(|room| {
+            banned = room.is_banned(ip, resume_token);
+            room.password_hash().map(str::to_string)
+        });
+        if banned {
+            app.unload_room_if_empty(room_name);
+            return Err(JoinError::Banned);
+        }
+        room_password_hash
+    };
+
+    let Some(password_hash) = room_password_hash else {
+        // The user is creating the room.
+        let password_hash = match password {
+            Some(password) => {
+                let password = password.to_string();
+                tokio::task::spawn_blocking(move || password::hash(&password))
+                    .await
+                    .ok()
+            }
+            None => None,
+        };
+        return Ok(CheckedRoom::New { password_hash });
+    };
+
+    // If the room doesn't require a password but one was given, just accept
+    // it.
+    if let Some(hash) = password_hash.clone() {
+        let err = match password {
+            None => Some(JoinError::PasswordRequired),
+            Some(password) => {
+                let password = password.to_string();
+                let correct =
+                    tokio::task::spawn_blocking(move || password::verify(&hash, &password))
+                        .await
+                        .unwrap_or(false);
+                (!correct).then_some(JoinError::PasswordIncorrect)
+            }
+        };
+        if let Some(err) = err {
+            app.write().unwrap().unload_room_if_empty(room_name);
+            return Err(err);
+        }
+    }
+    Ok(CheckedRoom::Existing { password_hash })
+}
+
 #[derive(Debug, Serialize)]
 #[serde(tag = "type", rename_all = "snake_case")]
 enum JoinError {
@@ -194,6 +447,7 @@ enum JoinError {
     ColorInvalid,
     PasswordRequired,
     PasswordIncorrect,
+    Banned,
 }
 impl JoinError {
     fn respond_websocket_error() -> Response<Body> {
@@ -231,6 +485,7 @@ impl JoinError {
                     // xx1x - Username
                     // xx2x - Color
                     // xx3x - Password
+                    // xx4x - Ban
                     Self::RoomMissing => CloseCode::Library(4000),
                     Self::RoomInvalidLength { .. } => CloseCode::Library(4002),
                     Self::UsernameMissing => CloseCode::Library(4010),
@@ -238,6 +493,7 @@ impl JoinError {
                     Self::ColorInvalid { .. } => CloseCode::Library(4021),
                     Self::PasswordRequired => CloseCode::Library(4030),
                     Self::PasswordIncorrect => CloseCode::Library(4033),
+                    Self::Banned => CloseCode::Library(4040),
                 },
             }))
             .await
@@ -256,15 +512,22 @@ async fn handle_request(
     app: AppState,
     req: Request<Incoming>,
     addr: SocketAddr,
+    client_ip_header: Option<HeaderName>,
 ) -> Result<Response<Body>, Infallible> {
     if !is_valid_request(&req) {
         return Ok(JoinError::respond_websocket_error());
     }
+    let ip = client_ip(&req, client_ip_header.as_ref(), addr);
 
     let headers = req.headers();
     let derived = headers
         .get(header::SEC_WEBSOCKET_KEY)
         .map(|k| tokio_tungstenite::tungstenite::handshake::derive_accept_key(k.as_bytes()));
+    let WebSocketProtocols {
+        protocol,
+        password: protocol_password,
+        resume_token: protocol_resume_token,
+    } = read_websocket_protocols(&req);
 
     let mut params: HashMap<String, String> = req
         .uri()
@@ -356,51 +619,80 @@ async fn handle_request(
                     .remove("canvas")
                     .and_then(|canvas| canvas.parse::<u16>().ok());
 
-                // Because the `app.read().unwrap()` holds a read guard, we can't
-                // just run `JoinError::<x>.respond_on_websocket().await`,
-                // as the read guard can't be held across the `.await`
-                let password = params.remove("password").filter(|p| !String::is_empty(p));
-                let mut err = None;
-                if let Some(room) = app.read().unwrap().get_room(&room_name) {
-                    // If the room doesn't require a password but one was given,
-                    // just accept it.
-                    if room.requires_password() {
-                        if password.is_none() {
-                            err = Some(JoinError::PasswordRequired);
-                        } else if !room.is_password_correct(password.as_deref()) {
-                            err = Some(JoinError::PasswordIncorrect);
-                        }
-                    }
-                }
-                if let Some(err) = err {
-                    return err.respond_on_websocket(socket.await).await;
-                }
+                // The password and resume token in the URL are only read for
+                // older clients.
+                let resume_token = protocol_resume_token
+                    .or_else(|| params.remove("resume_token"))
+                    .filter(|t| !String::is_empty(t));
+                let password = protocol_password
+                    .or_else(|| params.remove("password"))
+                    .filter(|p| !String::is_empty(p));
+
+                let checked = match check_can_join(
+                    &app,
+                    &room_name,
+                    password.as_deref(),
+                    ip,
+                    resume_token.as_deref(),
+                )
+                .await
+                {
+                    Ok(checked) => checked,
+                    Err(err) => return err.respond_on_websocket(socket.await).await,
+                };
 
                 let user = commands::join::Receive {
                     room_name: room_name.clone(),
+                    checked,
                     password,
                     username,
                     color,
                     canvas,
+                    resume_token,
                 };
 
-                handle_connection(app, user, socket.await, addr).await;
+                handle_connection(app, user, socket.await, addr, ip).await;
             }
             Err(e) => warn!("upgrade error: {}", e),
         }
     });
 
-    let res = Response::builder()
+    let mut res = Response::builder()
         .status(StatusCode::SWITCHING_PROTOCOLS)
         .version(ver)
         .header(CONNECTION, UPGRADE_HEADER_VALUE)
         .header(UPGRADE, WEBSOCKET_HEADER_VALUE)
-        .header(SEC_WEBSOCKET_ACCEPT, derived.unwrap())
-        .body(Body::default())
-        .unwrap();
+        .header(SEC_WEBSOCKET_ACCEPT, derived.unwrap());
+    // Never echo the password or resume token back.
+    if let Some(protocol) = protocol {
+        res = res.header(SEC_WEBSOCKET_PROTOCOL, protocol);
+    }
+    let res = res.body(Body::default()).unwrap();
     Ok(res)
 }
 
+/// Opens the store rooms are saved to.
+fn open_store(kind: StoreKind, data_dir: PathBuf) -> Arc<dyn RoomStore> {
+    match kind {
+        StoreKind::Memory => {
+            info!("Saving rooms in memory");
+            Arc::new(MemoryStore::new())
+        }
+        StoreKind::File => {
+            info!("Saving rooms to {data_dir:?}");
+            Arc::new(FileStore::new(data_dir))
+        }
+        StoreKind::Sqlite => {
+            let path = data_dir.join("rooms.sqlite3");
+            info!("Saving rooms to {path:?}");
+            match SqliteStore::open(&path) {
+                Ok(store) => Arc::new(store),
+                Err(e) => panic!("Could not open the room database at {path:?}.\n{e}"),
+            }
+        }
+    }
+}
+
 #[tokio::main]
 async fn main() {
     let cli = Cli::parse();
@@ -421,7 +713,9 @@ async fn main() {
     });
     info!("Hosting server on ws://{addr}");
 
-    let app: AppState = Arc::new(RwLock::new(App::new()));
+    let store = open_store(cli.store, cli.data_dir);
+    let admin_grace = Duration::from_secs(cli.admin_grace_secs);
+    let app: AppState = Arc::new(RwLock::new(App::new(store, admin_grace)));
 
     // Some connections auto-close the websocket after 30s-2m of receiving no data.
     // I believe Cloudflare does this. To prevent this, ping the client every
@@ -435,13 +729,81 @@ async fn main() {
         }
     });
 
+    let app_presences = app.clone();
+    tokio::spawn(async move {
+        let mut interval = time::interval(PRESENCE_INTERVAL);
+        interval.set_missed_tick_behavior(time::MissedTickBehavior::Skip);
+        loop {
+            interval.tick().await;
+            app_presences.write().unwrap().flush_presences();
+        }
+    });
+
+    let app_markers = app.clone();
+    tokio::spawn(async move {
+        let mut interval = time::interval(MARKER_SWEEP_INTERVAL);
+        interval.set_missed_tick_behavior(time::MissedTickBehavior::Skip);
+        loop {
+            interval.tick().await;
+            app_markers.write().unwrap().remove_expired_markers();
+        }
+    });
+
+    // Rooms are only kept around for so long after everyone leaves them,
+    // otherwise we'd slowly fill up the disk with rooms nobody will ever
+    // come back to.
+    let app_sweeper = app.clone();
+    let retention_days = cli.retention_days;
+    let retention = Duration::from_secs(retention_days * 24 * 60 * 60);
+    info!("Keeping rooms for {retention_days} days after everyone leaves");
+    tokio::spawn(async move {
+        let mut interval = time::interval(Duration::from_secs(60 * 60));
+        loop {
+            interval.tick().await;
+            // Only hold the lock long enough to see which rooms are open, as
+            // going through every saved room can take a while.
+            let (store, open_rooms) = {
+                let app = app_sweeper.read().unwrap();
+                (app.store(), app.open_room_names())
+            };
+            let deleted = tokio::task::spawn_blocking(move || {
+                store::delete_expired_rooms(&*store, &open_rooms, retention)
+            })
+            .await
+            .unwrap_or_default();
+            for room_name in deleted {
+                info!(
+                    "Deleted room `{room_name}` as nobody has been in it for {retention_days} days"
+                );
+            }
+        }
+    });
+
+    // Users whose connection dropped are kept in their room for a while so
+    // they can resume their session. Once that runs out, they're removed.
+    let app_disconnects = app.clone();
+    let resume_grace = Duration::from_secs(cli.resume_grace_secs);
+    tokio::spawn(async move {
+        let mut interval = time::interval(Duration::from_secs(5));
+        loop {
+            interval.tick().await;
+            app_disconnects
+                .write()
+                .unwrap()
+                .remove_expired_disconnects(resume_grace);
+        }
+    });
+
     loop {
         let (stream, remote_addr) = listener.accept().await.expect("Failed to accept request.");
         let app = app.clone();
+        let client_ip_header = cli.client_ip_header.clone();
 
         tokio::spawn(async move {
             let io = TokioIo::new(stream);
-            let service = service_fn(move |req| handle_request(app.clone(), req, remote_addr));
+            let service = service_fn(move |req| {
+                handle_request(app.clone(), req, remote_addr, client_ip_header.clone())
+            });
             let conn = http1::Builder::new()
                 .serve_connection(io, service)
                 .with_upgrades();
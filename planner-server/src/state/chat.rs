@@ -0,0 +1,21 @@
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// The longest chat message accepted, in bytes.
+pub const MAX_CHAT_MESSAGE_LEN: usize = 1000;
+/// The most chat messages kept in a room. Once there are more, the oldest are
+/// forgotten.
+pub const MAX_CHAT_HISTORY_LEN: usize = 200;
+
+/// A message sent in a room's chat.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ChatMessage {
+    pub uuid: Uuid,
+    /// The user who sent it.
+    pub user: Uuid,
+    /// The sender's username when they sent it, as they may have left since.
+    pub username: String,
+    pub content: String,
+    /// When it was sent, in milliseconds since the Unix epoch.
+    pub sent_at: u64,
+}
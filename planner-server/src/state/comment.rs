@@ -0,0 +1,39 @@
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// The longest comment accepted, in bytes.
+pub const MAX_COMMENT_LEN: usize = 2000;
+/// The most comments a thread can have.
+pub const MAX_THREAD_COMMENTS: usize = 200;
+/// The most threads a canvas can have, including archived ones.
+pub const MAX_CANVAS_THREADS: usize = 1000;
+
+/// A discussion about an element on a canvas.
+///
+/// Threads only refer to their element by its uuid, so they stay attached to
+/// it however it's changed.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Thread {
+    pub uuid: Uuid,
+    /// The element the thread is about.
+    pub element: Uuid,
+    /// The comments in the thread, oldest first. The first one started it.
+    pub comments: Vec<Comment>,
+    /// The user who marked the thread as resolved, if it is.
+    pub resolved_by: Option<Uuid>,
+    /// Whether the element was deleted. Archived threads can't be changed,
+    /// and are restored if the element is.
+    pub archived: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Comment {
+    pub uuid: Uuid,
+    /// The user who wrote the comment.
+    pub author: Uuid,
+    /// The author's username when they wrote it, as they may have left since.
+    pub author_name: String,
+    pub content: String,
+    /// When it was written, in milliseconds since the Unix epoch.
+    pub created_at: u64,
+}
@@ -1,9 +1,16 @@
 use std::collections::HashSet;
 
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
 use uuid::Uuid;
 
-use super::Color;
+use super::{unix_millis, Color};
+
+mod path;
+mod validate;
+
+pub use path::PathError;
+pub use validate::ValidationError;
 
 /// A single element on the canvas. For example, a piece of text, an image, etc.
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -13,10 +20,30 @@ pub struct Element {
     /// The type of element this is, along with all the additional information
     /// that comes with it.
     pub ty: ElementType,
-    /// The last user to have edited it.
+    /// How many times this element has been changed. This is set by the
+    /// server. Users can send back the version their change was made to, and
+    /// changes based on an older version are rejected. Changes without a
+    /// version are always accepted.
+    #[serde(default)]
+    pub version: Option<u64>,
+    /// The last user to have edited it. Set by the server.
+    #[serde(default)]
     pub last_edited_by: Option<Uuid>,
-    /// The editor or host who is currently selecting this element.
+    /// The editor or host who is currently selecting this element. Set by the
+    /// server.
+    #[serde(default)]
     pub selected_by: Option<Uuid>,
+    /// The user who created it. Set by the server.
+    #[serde(default)]
+    pub created_by: Option<Uuid>,
+    /// When it was created, in milliseconds since the Unix epoch. Set by the
+    /// server.
+    #[serde(default)]
+    pub created_at: u64,
+    /// When it was last edited, in milliseconds since the Unix epoch. Set by
+    /// the server.
+    #[serde(default)]
+    pub edited_at: u64,
     /// The X position of this element.
     pub x: f64,
     /// The Y position of this element.
@@ -41,8 +68,12 @@ impl Element {
         Element {
             uuid: Uuid::new_v4(),
             ty: el,
+            version: None,
             last_edited_by: None,
             selected_by: None,
+            created_by: None,
+            created_at: 0,
+            edited_at: 0,
             x: 0.,
             y: 0.,
             anchor: ElementAnchor::default(),
@@ -52,6 +83,132 @@ impl Element {
             tags: HashSet::default(),
         }
     }
+
+    /// The fields only the server can set. Clients can send them, but they
+    /// are replaced with the server's values.
+    pub const SERVER_OWNED_FIELDS: [&str; 6] = [
+        "version",
+        "last_edited_by",
+        "selected_by",
+        "created_by",
+        "created_at",
+        "edited_at",
+    ];
+
+    /// Replaces the fields only the server can set with their values from
+    /// the element's current state.
+    pub fn keep_server_owned_fields(&mut self, current: &Element) {
+        self.version = current.version;
+        self.last_edited_by = current.last_edited_by;
+        self.selected_by = current.selected_by;
+        self.created_by = current.created_by;
+        self.created_at = current.created_at;
+        self.edited_at = current.edited_at;
+    }
+
+    /// Sets the fields only the server can set for an element a user just
+    /// created. The user can create it already selected, but not selected by
+    /// anyone else.
+    pub fn mark_created(&mut self, user: Uuid) {
+        let now = unix_millis();
+        self.version = Some(0);
+        self.last_edited_by = Some(user);
+        self.selected_by = self.selected_by.filter(|&u| u == user);
+        self.created_by = Some(user);
+        self.created_at = now;
+        self.edited_at = now;
+    }
+
+    /// Updates the fields only the server can set after a user edited the
+    /// element.
+    pub fn mark_edited(&mut self, user: Uuid) {
+        self.version = Some(self.version.map_or(1, |v| v + 1));
+        self.last_edited_by = Some(user);
+        self.edited_at = unix_millis();
+    }
+
+    /// Returns this element with a patch applied to it.
+    ///
+    /// Fails if the patch changes the element's UUID, touches any
+    /// [server owned fields](Element::SERVER_OWNED_FIELDS), or leaves it in a
+    /// state that isn't a valid element.
+    pub fn patched(&self, patch: &ElementPatch) -> Result<Element, PatchError> {
+        if let Some(field) = Element::SERVER_OWNED_FIELDS
+            .into_iter()
+            .find(|field| patch.patch.contains_key(*field))
+        {
+            return Err(PatchError::ServerOwnedField {
+                field: field.to_string(),
+            });
+        }
+
+        let mut value = serde_json::to_value(self).map_err(PatchError::invalid)?;
+        merge_patch(&mut value, &patch.patch);
+        let el: Element = serde_json::from_value(value).map_err(PatchError::invalid)?;
+        if el.uuid != self.uuid {
+            return Err(PatchError::ChangesUuid);
+        }
+        Ok(el)
+    }
+}
+
+/// Changes to some of the fields of an element.
+///
+/// The patch is applied as a [JSON merge patch](https://www.rfc-editor.org/rfc/rfc7386):
+/// each field in it replaces the same field of the element, except for
+/// objects which are merged into the existing object instead. A field set to
+/// `null` is removed, which resets fields that are optional.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ElementPatch {
+    /// ID of the element to change.
+    pub uuid: Uuid,
+    /// The version of the element the patch was made to. If given, the patch
+    /// is rejected when the element has changed since then.
+    ///
+    /// When announced, this is the version of the element after the patch.
+    #[serde(default)]
+    pub version: Option<u64>,
+    pub patch: Map<String, Value>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "reason", rename_all = "snake_case")]
+pub enum PatchError {
+    /// Elements can't change their UUID.
+    ChangesUuid,
+    /// The patch tried to change a field only the server can set.
+    ServerOwnedField { field: String },
+    /// The patched element wasn't a valid element.
+    Invalid { message: String },
+}
+impl PatchError {
+    fn invalid(e: serde_json::Error) -> Self {
+        PatchError::Invalid {
+            message: e.to_string(),
+        }
+    }
+}
+
+fn merge_patch(target: &mut Value, patch: &Map<String, Value>) {
+    if !target.is_object() {
+        *target = Value::Object(Map::new());
+    }
+    let Value::Object(target) = target else {
+        unreachable!()
+    };
+    for (key, value) in patch {
+        match value {
+            Value::Null => {
+                target.remove(key);
+            }
+            Value::Object(patch) => {
+                merge_patch(target.entry(key).or_insert(Value::Null), patch);
+            }
+            value => {
+                target.insert(key.clone(), value.clone());
+            }
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -59,6 +216,12 @@ impl Element {
 pub enum ElementType {
     Text(ElementText),
     Image(ElementImage),
+    Path(ElementPath),
+    Line(ElementLine),
+    Arrow(ElementArrow),
+    Rectangle(ElementRectangle),
+    Ellipse(ElementEllipse),
+    Polygon(ElementPolygon),
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -172,6 +335,116 @@ pub struct ImageText {
     text: ElementText,
 }
 
+/// A freehand drawing, stored as an SVG path so it stays sharp at every zoom
+/// level.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ElementPath {
+    /// The path data, in the same format as the `d` attribute of an SVG
+    /// `<path>`. Coordinates are relative to the element's position.
+    d: String,
+    stroke: Stroke,
+    /// The colour to fill the inside of the path with, if any.
+    fill: Option<Color>,
+}
+impl ElementPath {
+    /// Checks the path data is valid and isn't too complex.
+    pub fn validate(&self) -> Result<(), PathError> {
+        path::count_points(&self.d).map(|_| ())
+    }
+}
+
+/// A line through a list of points.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ElementLine {
+    /// The points the line goes through, in order. Relative to the element's
+    /// position.
+    points: Vec<Point>,
+    stroke: Stroke,
+}
+
+/// A line through a list of points, with a head at either end.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ElementArrow {
+    /// The points the arrow goes through, from its tail to its head. Relative
+    /// to the element's position.
+    points: Vec<Point>,
+    stroke: Stroke,
+    /// The head drawn at the first point.
+    start_head: ArrowHead,
+    /// The head drawn at the last point.
+    end_head: ArrowHead,
+}
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ArrowHead {
+    None,
+    /// A filled triangle.
+    #[default]
+    Triangle,
+    /// Two lines in a `>` shape.
+    Open,
+    Circle,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ElementRectangle {
+    width: f64,
+    height: f64,
+    /// How rounded the corners are. 0 gives square corners.
+    corner_radius: f64,
+    /// The outline of the rectangle, if it has one.
+    stroke: Option<Stroke>,
+    /// The colour to fill the rectangle with, if any.
+    fill: Option<Color>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ElementEllipse {
+    /// The width of the ellipse, from its left edge to its right.
+    width: f64,
+    /// The height of the ellipse, from its top edge to its bottom.
+    height: f64,
+    /// The outline of the ellipse, if it has one.
+    stroke: Option<Stroke>,
+    /// The colour to fill the ellipse with, if any.
+    fill: Option<Color>,
+}
+
+/// A closed shape with a corner at each point.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ElementPolygon {
+    /// The corners of the polygon, in order. Relative to the element's
+    /// position.
+    points: Vec<Point>,
+    /// The outline of the polygon, if it has one.
+    stroke: Option<Stroke>,
+    /// The colour to fill the polygon with, if any.
+    fill: Option<Color>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct Point {
+    x: f64,
+    y: f64,
+}
+
+/// How a line or the outline of a shape is drawn.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Stroke {
+    color: Color,
+    /// The thickness of the line.
+    width: f64,
+    dash: StrokeDash,
+}
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum StrokeDash {
+    #[default]
+    Solid,
+    Dashed,
+    Dotted,
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct ElementAnchor {
     /// Position from the top, as a float of 0-1 representing a percentage.
@@ -0,0 +1,255 @@
+//! Validation of SVG path data, as used in the `d` attribute of a `<path>`.
+//!
+//! This doesn't build up the path, it only checks it is well-formed and
+//! counts how many points it has so overly complex drawings can be rejected.
+
+use serde::Serialize;
+
+/// The longest path data accepted, in bytes.
+pub const MAX_PATH_LEN: usize = 256 * 1024;
+/// The most points (the end of each segment) a path can have.
+pub const MAX_PATH_POINTS: usize = 10_000;
+
+#[derive(Serialize, Debug, PartialEq, Eq)]
+#[serde(tag = "reason", rename_all = "snake_case")]
+pub enum PathError {
+    TooLong {
+        max_len: usize,
+    },
+    TooManyPoints {
+        max_points: usize,
+    },
+    /// The path didn't start with a `M` or `m` command.
+    MissingMoveTo,
+    /// The path couldn't be parsed at the given byte offset.
+    Invalid {
+        position: usize,
+    },
+}
+
+/// Checks the path data is valid, returning how many points it has.
+pub fn count_points(d: &str) -> Result<usize, PathError> {
+    if d.len() > MAX_PATH_LEN {
+        return Err(PathError::TooLong {
+            max_len: MAX_PATH_LEN,
+        });
+    }
+
+    let mut parser = Parser { d, pos: 0 };
+    let mut points = 0;
+    let mut is_first = true;
+    while let Some(command) = parser.next_command()? {
+        if is_first && !matches!(command, b'M' | b'm') {
+            return Err(PathError::MissingMoveTo);
+        }
+        is_first = false;
+
+        if matches!(command, b'Z' | b'z') {
+            continue;
+        }
+        // Commands can be followed by any number of sets of arguments, each
+        // of which is another segment.
+        loop {
+            parser.segment_args(command)?;
+            points += 1;
+            if points > MAX_PATH_POINTS {
+                return Err(PathError::TooManyPoints {
+                    max_points: MAX_PATH_POINTS,
+                });
+            }
+            if !parser.has_number_next() {
+                break;
+            }
+        }
+    }
+    Ok(points)
+}
+
+struct Parser<'a> {
+    d: &'a str,
+    pos: usize,
+}
+impl Parser<'_> {
+    fn invalid(&self) -> PathError {
+        PathError::Invalid { position: self.pos }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.d.as_bytes().get(self.pos).copied()
+    }
+
+    fn skip_separators(&mut self) {
+        while self
+            .peek()
+            .is_some_and(|c| c.is_ascii_whitespace() || c == b',')
+        {
+            self.pos += 1;
+        }
+    }
+
+    fn has_number_next(&mut self) -> bool {
+        self.skip_separators();
+        self.peek()
+            .is_some_and(|c| c.is_ascii_digit() || matches!(c, b'-' | b'+' | b'.'))
+    }
+
+    /// Reads the next command letter, or `None` at the end of the path.
+    fn next_command(&mut self) -> Result<Option<u8>, PathError> {
+        self.skip_separators();
+        match self.peek() {
+            None => Ok(None),
+            Some(c) if b"MmLlHhVvCcSsQqTtAaZz".contains(&c) => {
+                self.pos += 1;
+                Ok(Some(c))
+            }
+            Some(_) => Err(self.invalid()),
+        }
+    }
+
+    /// Reads one segment's worth of arguments for a command.
+    fn segment_args(&mut self, command: u8) -> Result<(), PathError> {
+        match command.to_ascii_uppercase() {
+            b'M' | b'L' | b'T' => self.numbers(2),
+            b'H' | b'V' => self.numbers(1),
+            b'S' | b'Q' => self.numbers(4),
+            b'C' => self.numbers(6),
+            b'A' => {
+                // rx ry x-axis-rotation large-arc-flag sweep-flag x y
+                self.numbers(3)?;
+                self.flag()?;
+                self.flag()?;
+                self.numbers(2)
+            }
+            _ => Err(self.invalid()),
+        }
+    }
+
+    fn numbers(&mut self, count: usize) -> Result<(), PathError> {
+        for _ in 0..count {
+            self.number()?;
+        }
+        Ok(())
+    }
+
+    /// Arc flags are a single `0` or `1`, and don't need a separator after
+    /// them.
+    fn flag(&mut self) -> Result<(), PathError> {
+        self.skip_separators();
+        match self.peek() {
+            Some(b'0' | b'1') => {
+                self.pos += 1;
+                Ok(())
+            }
+            _ => Err(self.invalid()),
+        }
+    }
+
+    fn number(&mut self) -> Result<f64, PathError> {
+        self.skip_separators();
+        let start = self.pos;
+        let bytes = self.d.as_bytes();
+        let mut end = start;
+        if matches!(bytes.get(end), Some(b'-' | b'+')) {
+            end += 1;
+        }
+        let mut seen_dot = false;
+        while let Some(&c) = bytes.get(end) {
+            if c.is_ascii_digit() {
+                end += 1;
+            } else if c == b'.' && !seen_dot {
+                seen_dot = true;
+                end += 1;
+            } else {
+                break;
+            }
+        }
+        if matches!(bytes.get(end), Some(b'e' | b'E')) {
+            end += 1;
+            if matches!(bytes.get(end), Some(b'-' | b'+')) {
+                end += 1;
+            }
+            while bytes.get(end).is_some_and(u8::is_ascii_digit) {
+                end += 1;
+            }
+        }
+
+        let number = self.d[start..end]
+            .parse::<f64>()
+            .ok()
+            .filter(|n| n.is_finite())
+            .ok_or_else(|| self.invalid())?;
+        self.pos = end;
+        Ok(number)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_points() {
+        assert_eq!(count_points(""), Ok(0));
+        assert_eq!(count_points("M 10 10 L 20 20 Z"), Ok(2));
+        assert_eq!(
+            count_points("m0 0 h5 v5 c1 1 2 2 3 3 s1 1 2 2 q1 1 2 2 t3 3 z"),
+            Ok(7)
+        );
+    }
+
+    #[test]
+    fn repeated_arguments_are_more_segments() {
+        assert_eq!(count_points("M0,0 10,10 20,20"), Ok(3));
+        assert_eq!(count_points("M0 0 L1 1 2 2 3 3"), Ok(4));
+    }
+
+    #[test]
+    fn compact_numbers_and_flags() {
+        assert_eq!(count_points("M1-2.5.5.5"), Ok(2));
+        assert_eq!(count_points("M0 0a10 10 0 0110 10"), Ok(2));
+        assert_eq!(count_points("M1e2 -1E-2"), Ok(1));
+    }
+
+    #[test]
+    fn must_start_with_move_to() {
+        assert_eq!(count_points("L 1 1"), Err(PathError::MissingMoveTo));
+    }
+
+    #[test]
+    fn invalid_paths() {
+        assert_eq!(
+            count_points("M 0 0 X"),
+            Err(PathError::Invalid { position: 6 })
+        );
+        assert_eq!(count_points("M 0"), Err(PathError::Invalid { position: 3 }));
+        assert_eq!(
+            count_points("M 1e999 0"),
+            Err(PathError::Invalid { position: 2 })
+        );
+        assert_eq!(
+            count_points("M0 0 A1 1 0 2 0 1 1"),
+            Err(PathError::Invalid { position: 12 })
+        );
+    }
+
+    #[test]
+    fn limits() {
+        let long = format!("M0 0{}", " ".repeat(MAX_PATH_LEN));
+        assert_eq!(
+            count_points(&long),
+            Err(PathError::TooLong {
+                max_len: MAX_PATH_LEN
+            })
+        );
+
+        let many = format!("M0 0{}", " 1 1".repeat(MAX_PATH_POINTS));
+        assert_eq!(
+            count_points(&many),
+            Err(PathError::TooManyPoints {
+                max_points: MAX_PATH_POINTS
+            })
+        );
+        let most = format!("M0 0{}", " 1 1".repeat(MAX_PATH_POINTS - 1));
+        assert_eq!(count_points(&most), Ok(MAX_PATH_POINTS));
+    }
+}
@@ -0,0 +1,247 @@
+//! Checks elements sent by clients are sensible before they're stored.
+
+use super::{
+    path::PathError, Element, ElementAnchor, ElementArrow, ElementEllipse, ElementImage,
+    ElementLine, ElementPath, ElementPolygon, ElementRectangle, ElementText, ElementType,
+    ImageText, Point, Stroke, TextFont,
+};
+use serde::Serialize;
+
+/// The longest text content accepted, in bytes.
+const MAX_TEXT_LEN: usize = 10_000;
+/// The longest image URL accepted, in bytes.
+const MAX_URL_LEN: usize = 2048;
+/// The longest image alt text accepted, in bytes.
+const MAX_ALT_LEN: usize = 1000;
+/// The longest custom font family accepted, in bytes.
+const MAX_FONT_FAMILY_LEN: usize = 128;
+/// The most pieces of text that can be placed on an image.
+const MAX_IMAGE_TEXTS: usize = 32;
+/// The most points a line, arrow or polygon can have.
+const MAX_POINTS: usize = 1000;
+/// The most tags an element can have.
+const MAX_TAGS: usize = 32;
+/// The longest tag accepted, in bytes.
+const MAX_TAG_LEN: usize = 64;
+/// The largest font size, stroke width, blur, etc.
+const MAX_SIZE: f64 = 1000.;
+/// The largest width or height of a shape, or scale of an image.
+const MAX_DIMENSION: f64 = 100_000.;
+
+/// Why an element isn't valid.
+#[derive(Serialize, Debug)]
+pub struct ValidationError {
+    /// The path to the field that was invalid, for example `ty.crop.left`.
+    pub field: String,
+    #[serde(flatten)]
+    pub reason: InvalidReason,
+}
+impl ValidationError {
+    fn new(field: &str, reason: InvalidReason) -> Self {
+        ValidationError {
+            field: field.to_string(),
+            reason,
+        }
+    }
+
+    /// Puts the field this error is for inside another field.
+    fn within(mut self, parent: &str) -> Self {
+        self.field = format!("{parent}.{}", self.field);
+        self
+    }
+}
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "reason", rename_all = "snake_case")]
+pub enum InvalidReason {
+    /// The number was NaN or infinite.
+    NotFinite,
+    OutOfRange {
+        min: f64,
+        max: f64,
+    },
+    TooLong {
+        max_len: usize,
+    },
+    TooMany {
+        max: usize,
+    },
+    TooFew {
+        min: usize,
+    },
+    InvalidPath {
+        error: PathError,
+    },
+}
+
+type Result = std::result::Result<(), ValidationError>;
+
+impl Element {
+    /// Checks every field of the element is within sensible limits.
+    pub fn validate(&self) -> Result {
+        finite("x", self.x)?;
+        finite("y", self.y)?;
+        finite("rotation", self.rotation)?;
+        finite("z_index", self.z_index)?;
+        validate_anchor(&self.anchor).map_err(|e| e.within("anchor"))?;
+        max_count("tags", self.tags.len(), MAX_TAGS)?;
+        for tag in &self.tags {
+            max_len("tags", tag, MAX_TAG_LEN)?;
+        }
+
+        match &self.ty {
+            ElementType::Text(text) => validate_text(text),
+            ElementType::Image(image) => validate_image(image),
+            ElementType::Path(path) => validate_path(path),
+            ElementType::Line(line) => validate_line(line),
+            ElementType::Arrow(arrow) => validate_arrow(arrow),
+            ElementType::Rectangle(rectangle) => validate_rectangle(rectangle),
+            ElementType::Ellipse(ellipse) => validate_ellipse(ellipse),
+            ElementType::Polygon(polygon) => validate_polygon(polygon),
+        }
+        .map_err(|e| e.within("ty"))
+    }
+}
+
+fn validate_text(text: &ElementText) -> Result {
+    max_len("content", &text.content, MAX_TEXT_LEN)?;
+    in_range("size", text.size.into(), 0., MAX_SIZE)?;
+    in_range("background_blur", text.background_blur, 0., MAX_SIZE)?;
+    match &text.font {
+        TextFont::Sans(Some(family))
+        | TextFont::Serif(Some(family))
+        | TextFont::Mono(Some(family)) => {
+            max_len("font.custom_font_family", family, MAX_FONT_FAMILY_LEN)
+        }
+        _ => Ok(()),
+    }
+}
+
+fn validate_image(image: &ElementImage) -> Result {
+    max_len("url", &image.url, MAX_URL_LEN)?;
+    max_len("alt", &image.alt, MAX_ALT_LEN)?;
+    in_range("scale_x", image.scale_x, 0., MAX_DIMENSION)?;
+    in_range("scale_y", image.scale_y, 0., MAX_DIMENSION)?;
+    in_range("crop.left", image.crop.left, 0., 1.)?;
+    in_range("crop.top", image.crop.top, 0., 1.)?;
+    in_range("crop.right", image.crop.right, 0., 1.)?;
+    in_range("crop.bottom", image.crop.bottom, 0., 1.)?;
+    in_range("outline_thickness", image.outline_thickness, 0., MAX_SIZE)?;
+    in_range("outline_blur", image.outline_blur, 0., MAX_SIZE)?;
+    max_count("text", image.text.len(), MAX_IMAGE_TEXTS)?;
+    for (i, text) in image.text.iter().enumerate() {
+        validate_image_text(text).map_err(|e| e.within(&format!("text[{i}]")))?;
+    }
+    Ok(())
+}
+
+fn validate_image_text(text: &ImageText) -> Result {
+    in_range("x", text.x, 0., 1.)?;
+    in_range("y", text.y, 0., 1.)?;
+    validate_anchor(&text.anchor).map_err(|e| e.within("anchor"))?;
+    validate_text(&text.text).map_err(|e| e.within("text"))
+}
+
+fn validate_path(path: &ElementPath) -> Result {
+    path.validate()
+        .map_err(|error| ValidationError::new("d", InvalidReason::InvalidPath { error }))?;
+    validate_stroke(&path.stroke).map_err(|e| e.within("stroke"))
+}
+
+fn validate_line(line: &ElementLine) -> Result {
+    validate_points(&line.points, 2)?;
+    validate_stroke(&line.stroke).map_err(|e| e.within("stroke"))
+}
+
+fn validate_arrow(arrow: &ElementArrow) -> Result {
+    validate_points(&arrow.points, 2)?;
+    validate_stroke(&arrow.stroke).map_err(|e| e.within("stroke"))
+}
+
+fn validate_rectangle(rectangle: &ElementRectangle) -> Result {
+    in_range("width", rectangle.width, 0., MAX_DIMENSION)?;
+    in_range("height", rectangle.height, 0., MAX_DIMENSION)?;
+    in_range("corner_radius", rectangle.corner_radius, 0., MAX_DIMENSION)?;
+    validate_optional_stroke(&rectangle.stroke)
+}
+
+fn validate_ellipse(ellipse: &ElementEllipse) -> Result {
+    in_range("width", ellipse.width, 0., MAX_DIMENSION)?;
+    in_range("height", ellipse.height, 0., MAX_DIMENSION)?;
+    validate_optional_stroke(&ellipse.stroke)
+}
+
+fn validate_polygon(polygon: &ElementPolygon) -> Result {
+    validate_points(&polygon.points, 3)?;
+    validate_optional_stroke(&polygon.stroke)
+}
+
+fn validate_points(points: &[Point], min: usize) -> Result {
+    if points.len() < min {
+        return Err(ValidationError::new(
+            "points",
+            InvalidReason::TooFew { min },
+        ));
+    }
+    max_count("points", points.len(), MAX_POINTS)?;
+    for (i, point) in points.iter().enumerate() {
+        finite(&format!("points[{i}].x"), point.x)?;
+        finite(&format!("points[{i}].y"), point.y)?;
+    }
+    Ok(())
+}
+
+fn validate_stroke(stroke: &Stroke) -> Result {
+    in_range("width", stroke.width, 0., MAX_SIZE)
+}
+
+fn validate_optional_stroke(stroke: &Option<Stroke>) -> Result {
+    match stroke {
+        Some(stroke) => validate_stroke(stroke).map_err(|e| e.within("stroke")),
+        None => Ok(()),
+    }
+}
+
+fn validate_anchor(anchor: &ElementAnchor) -> Result {
+    in_range("top", anchor.top, 0., 1.)?;
+    in_range("left", anchor.left, 0., 1.)
+}
+
+fn finite(field: &str, value: f64) -> Result {
+    if value.is_finite() {
+        Ok(())
+    } else {
+        Err(ValidationError::new(field, InvalidReason::NotFinite))
+    }
+}
+
+fn in_range(field: &str, value: f64, min: f64, max: f64) -> Result {
+    finite(field, value)?;
+    if (min..=max).contains(&value) {
+        Ok(())
+    } else {
+        Err(ValidationError::new(
+            field,
+            InvalidReason::OutOfRange { min, max },
+        ))
+    }
+}
+
+fn max_len(field: &str, value: &str, max_len: usize) -> Result {
+    if value.len() <= max_len {
+        Ok(())
+    } else {
+        Err(ValidationError::new(
+            field,
+            InvalidReason::TooLong { max_len },
+        ))
+    }
+}
+
+fn max_count(field: &str, count: usize, max: usize) -> Result {
+    if count <= max {
+        Ok(())
+    } else {
+        Err(ValidationError::new(field, InvalidReason::TooMany { max }))
+    }
+}
@@ -0,0 +1,347 @@
+use super::{comment::Thread, element::Element};
+use std::{
+    collections::BTreeMap,
+    time::{Duration, Instant},
+};
+use uuid::Uuid;
+
+/// The most changes kept in a canvas's history. Once there are more, the
+/// oldest are forgotten.
+const MAX_HISTORY_LEN: usize = 250;
+/// How soon after a patch another patch to the same elements by the same user
+/// is merged into it, so something like a drag is undone all at once.
+const PATCH_MERGE_WINDOW: Duration = Duration::from_secs(1);
+
+/// The undo/redo history of a canvas.
+///
+/// History is a tree rather than a list. Making a change after undoing
+/// something starts a new branch instead of throwing away what was undone,
+/// and redoing follows whichever branch was most recently made or redone.
+///
+/// Each user undoes and redoes their own changes. If other users made changes
+/// since, a user's changes are undone where they are in the tree, without
+/// undoing anything that came after.
+#[derive(Debug, Default, Clone)]
+pub struct History {
+    nodes: BTreeMap<u64, HistoryNode>,
+    /// The id the next node will be given. Ids only ever increase, so the
+    /// first node in `nodes` is always the oldest.
+    next_id: u64,
+    /// The node whose changes were applied most recently, or `None` if
+    /// everything has been undone.
+    current: Option<u64>,
+    /// The node that redoing from the very start of the history goes to.
+    root_redo_child: Option<u64>,
+    /// How many times anything was undone, used to find the most recently
+    /// undone node.
+    undo_count: u64,
+}
+
+#[derive(Debug, Clone)]
+struct HistoryNode {
+    parent: Option<u64>,
+    /// The user who made these changes.
+    user: Uuid,
+    changes: Vec<ElementChange>,
+    /// The child that redoing goes to.
+    redo_child: Option<u64>,
+    /// When the node was last changed by a patch, if it was made by patches.
+    patched_at: Option<Instant>,
+    /// If the node's changes are undone, the value of
+    /// [`History::undo_count`] when they were.
+    undone: Option<u64>,
+}
+
+/// A change made to a single element.
+#[derive(Debug, Clone)]
+pub struct ElementChange {
+    /// The element before the change, or `None` if it was created.
+    pub before: Option<Element>,
+    /// The element after the change, or `None` if it was deleted.
+    pub after: Option<Element>,
+}
+impl ElementChange {
+    /// The uuid of the changed element.
+    fn uuid(&self) -> Uuid {
+        self.before
+            .as_ref()
+            .or(self.after.as_ref())
+            .map(|el| el.uuid)
+            .expect("a change has an element before or after it")
+    }
+}
+
+/// The elements that changed from undoing or redoing.
+#[derive(Debug, Default)]
+pub struct AppliedChanges {
+    pub elements: Vec<Element>,
+    pub deleted_elements: Vec<Uuid>,
+    /// Threads that were archived or restored along with their element.
+    pub threads: Vec<Thread>,
+}
+
+impl History {
+    /// Records changes a user made, as a child of the current node.
+    pub fn record(&mut self, user: Uuid, changes: Vec<ElementChange>) {
+        self.insert(user, changes, None);
+    }
+
+    /// Records changes a user made by patching elements.
+    ///
+    /// If the current node is the user's own patch to the same elements from
+    /// moments ago, and nothing was undone since, the changes are merged into
+    /// it instead of making a new node.
+    pub fn record_patch(&mut self, user: Uuid, changes: Vec<ElementChange>) {
+        if changes.is_empty() {
+            return;
+        }
+
+        let now = Instant::now();
+        if let Some(id) = self.current
+            && let Some(node) = self.nodes.get_mut(&id)
+            && node.user == user
+            && node.undone.is_none()
+            && node.redo_child.is_none()
+            && node
+                .patched_at
+                .is_some_and(|at| now.duration_since(at) < PATCH_MERGE_WINDOW)
+            && same_elements(&node.changes, &changes)
+        {
+            for (change, merged) in node.changes.iter_mut().zip(changes) {
+                change.after = merged.after;
+            }
+            node.patched_at = Some(now);
+            return;
+        }
+
+        self.insert(user, changes, Some(now));
+    }
+
+    fn insert(&mut self, user: Uuid, changes: Vec<ElementChange>, patched_at: Option<Instant>) {
+        if changes.is_empty() {
+            return;
+        }
+
+        let id = self.next_id;
+        self.next_id += 1;
+        self.nodes.insert(
+            id,
+            HistoryNode {
+                parent: self.current,
+                user,
+                changes,
+                redo_child: None,
+                patched_at,
+                undone: None,
+            },
+        );
+        self.set_redo_child(self.current, Some(id));
+        self.current = Some(id);
+
+        while self.nodes.len() > MAX_HISTORY_LEN {
+            self.forget_oldest();
+        }
+    }
+
+    /// Undoes the latest changes `user` made that are still applied.
+    ///
+    /// This is refused if someone else edited any of the changed elements
+    /// since, or if another editor has one of them selected.
+    pub fn undo(
+        &mut self,
+        user: Uuid,
+        elements: &mut Vec<Element>,
+    ) -> Result<AppliedChanges, HistoryError> {
+        let Some(id) = self
+            .ancestors()
+            .find(|(_, node)| node.user == user && node.undone.is_none())
+            .map(|(id, _)| id)
+        else {
+            return Err(HistoryError::NothingToUndo);
+        };
+        let node = &self.nodes[&id];
+        check_can_apply(node.changes.iter().map(|c| &c.after), user, elements)?;
+
+        let applied = apply(
+            node.changes.iter().rev().map(|c| (&c.after, &c.before)),
+            user,
+            elements,
+        );
+        let parent = node.parent;
+        self.undo_count += 1;
+        self.nodes
+            .get_mut(&id)
+            .expect("the node was just found")
+            .undone = Some(self.undo_count);
+        if self.current == Some(id) {
+            self.current = parent;
+        }
+        Ok(applied)
+    }
+
+    /// Redoes the changes `user` most recently undid, either along the
+    /// current branch or where they are in the tree.
+    ///
+    /// The same rules as [`History::undo`] apply.
+    pub fn redo(
+        &mut self,
+        user: Uuid,
+        elements: &mut Vec<Element>,
+    ) -> Result<AppliedChanges, HistoryError> {
+        let redo_child = match self.current {
+            Some(id) => self.nodes[&id].redo_child,
+            None => self.root_redo_child,
+        };
+        let Some(id) = redo_child
+            .into_iter()
+            .map(|id| (id, &self.nodes[&id]))
+            .chain(self.ancestors())
+            .filter(|(_, node)| node.user == user)
+            .filter_map(|(id, node)| Some((id, node.undone?)))
+            .max_by_key(|(_, undone)| *undone)
+            .map(|(id, _)| id)
+        else {
+            return Err(HistoryError::NothingToRedo);
+        };
+        let node = &self.nodes[&id];
+        check_can_apply(node.changes.iter().map(|c| &c.before), user, elements)?;
+
+        let applied = apply(
+            node.changes.iter().map(|c| (&c.before, &c.after)),
+            user,
+            elements,
+        );
+        self.nodes
+            .get_mut(&id)
+            .expect("the node was just found")
+            .undone = None;
+        if redo_child == Some(id) {
+            self.current = Some(id);
+        }
+        Ok(applied)
+    }
+
+    /// The current node and all of its ancestors, newest first.
+    fn ancestors(&self) -> impl Iterator<Item = (u64, &HistoryNode)> {
+        std::iter::successors(
+            self.current.and_then(|id| Some((id, self.nodes.get(&id)?))),
+            |(_, node)| {
+                let parent = node.parent?;
+                Some((parent, self.nodes.get(&parent)?))
+            },
+        )
+    }
+
+    fn set_redo_child(&mut self, parent: Option<u64>, child: Option<u64>) {
+        match parent {
+            Some(parent) => {
+                if let Some(parent) = self.nodes.get_mut(&parent) {
+                    parent.redo_child = child;
+                }
+            }
+            None => self.root_redo_child = child,
+        }
+    }
+
+    /// Forgets the oldest node. Its children become the start of the history.
+    fn forget_oldest(&mut self) {
+        let Some((id, node)) = self.nodes.pop_first() else {
+            return;
+        };
+        for child in self.nodes.values_mut() {
+            if child.parent == Some(id) {
+                child.parent = None;
+            }
+        }
+        if self.root_redo_child == Some(id) {
+            self.root_redo_child = node.redo_child;
+        }
+        if self.current == Some(id) {
+            self.current = None;
+        }
+    }
+}
+
+/// Checks the user is allowed to undo or redo changes, given the state each
+/// element should be in for that, where `None` means it shouldn't exist.
+fn check_can_apply<'a>(
+    expected: impl Iterator<Item = &'a Option<Element>>,
+    user: Uuid,
+    elements: &[Element],
+) -> Result<(), HistoryError> {
+    for expected in expected {
+        let Some(expected) = expected else {
+            continue;
+        };
+        let uuid = expected.uuid;
+        let Some(el) = elements.iter().find(|el| el.uuid == uuid) else {
+            // Someone else deleted it.
+            return Err(HistoryError::NotLastEditor { element: uuid });
+        };
+        if el.last_edited_by != Some(user) {
+            return Err(HistoryError::NotLastEditor { element: uuid });
+        }
+        if el.selected_by.is_some_and(|u| u != user) {
+            return Err(HistoryError::ElementSelected { element: uuid });
+        }
+    }
+    Ok(())
+}
+
+/// Whether two lists of changes are to the same elements, in the same order.
+fn same_elements(a: &[ElementChange], b: &[ElementChange]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).all(|(a, b)| a.uuid() == b.uuid())
+}
+
+/// Changes each element from one state to another.
+fn apply<'a>(
+    changes: impl Iterator<Item = (&'a Option<Element>, &'a Option<Element>)>,
+    user: Uuid,
+    elements: &mut Vec<Element>,
+) -> AppliedChanges {
+    let mut applied = AppliedChanges::default();
+    for (from, to) in changes {
+        match (from, to) {
+            (_, Some(to)) => {
+                let mut to = to.clone();
+                match elements.iter_mut().find(|el| el.uuid == to.uuid) {
+                    Some(el) => {
+                        // Keep whoever has it selected right now, not whoever
+                        // had it selected back then.
+                        to.selected_by = el.selected_by;
+                        to.version = el.version;
+                        to.mark_edited(user);
+                        *el = to.clone();
+                    }
+                    None => {
+                        to.selected_by = None;
+                        to.mark_edited(user);
+                        elements.push(to.clone());
+                    }
+                }
+                applied.elements.push(to);
+            }
+            (Some(from), None) => {
+                elements.retain(|el| el.uuid != from.uuid);
+                applied.deleted_elements.push(from.uuid);
+            }
+            (None, None) => {}
+        }
+    }
+    applied
+}
+
+/// Why changes couldn't be undone or redone.
+#[derive(Debug)]
+pub enum HistoryError {
+    NothingToUndo,
+    NothingToRedo,
+    /// Someone else changed one of the elements since.
+    NotLastEditor {
+        element: Uuid,
+    },
+    /// Another editor has one of the elements selected.
+    ElementSelected {
+        element: Uuid,
+    },
+}
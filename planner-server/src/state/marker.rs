@@ -0,0 +1,96 @@
+//! Short-lived markers users can put on a canvas, like pings and laser
+//! pointer trails.
+//!
+//! Markers are only ever sent to users on the same canvas. They aren't
+//! elements, so they're never saved or added to the undo history, and they're
+//! removed by the server once they expire.
+
+use super::user::Position;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+/// How long a ping stays on the canvas.
+pub const PING_TTL: Duration = Duration::from_secs(5);
+/// How long each part of a laser trail stays on the canvas.
+pub const LASER_TTL: Duration = Duration::from_millis(1500);
+/// The most points a single part of a laser trail can have.
+pub const MAX_LASER_POINTS: usize = 100;
+
+/// How many pings a user can place within [`PING_RATE_WINDOW`].
+pub const MAX_PINGS_PER_WINDOW: u32 = 5;
+pub const PING_RATE_WINDOW: Duration = Duration::from_secs(5);
+/// How many parts of a laser trail a user can send within
+/// [`LASER_RATE_WINDOW`].
+pub const MAX_LASERS_PER_WINDOW: u32 = 30;
+pub const LASER_RATE_WINDOW: Duration = Duration::from_secs(1);
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Marker {
+    pub uuid: Uuid,
+    /// The user who placed it.
+    pub user: Uuid,
+    #[serde(flatten)]
+    pub ty: MarkerType,
+    /// When the marker will be removed, in milliseconds since the Unix epoch.
+    pub expires_at: u64,
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum MarkerType {
+    Ping {
+        kind: PingKind,
+        position: Position,
+    },
+    /// Part of the trail left by a user's laser pointer.
+    Laser {
+        points: Vec<Position>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum PingKind {
+    Generic,
+    GoHere,
+    Danger,
+}
+
+/// A marker that's currently on a canvas.
+#[derive(Debug)]
+pub struct ActiveMarker {
+    pub canvas: u16,
+    pub expires: Instant,
+    pub marker: Marker,
+}
+
+/// Limits how often something can be done, by counting how many times it
+/// was done since the start of the current window.
+#[derive(Debug, Clone)]
+pub struct RateLimit {
+    window_start: Instant,
+    count: u32,
+}
+impl RateLimit {
+    pub fn new() -> Self {
+        RateLimit {
+            window_start: Instant::now(),
+            count: 0,
+        }
+    }
+
+    /// Counts another use, returning whether it's within the limit.
+    pub fn check(&mut self, max: u32, window: Duration) -> bool {
+        let now = Instant::now();
+        if now.duration_since(self.window_start) >= window {
+            self.window_start = now;
+            self.count = 0;
+        }
+        if self.count >= max {
+            return false;
+        }
+        self.count += 1;
+        true
+    }
+}
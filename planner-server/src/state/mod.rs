@@ -1,48 +1,119 @@
 use crate::commands::{self, AnnounceTo, AnnounceType, ErrorType};
+use chat::ChatMessage;
+use comment::Thread;
 use element::{Element, ElementText, ElementType};
 use futures_channel::mpsc::UnboundedSender;
+use history::{AppliedChanges, History, HistoryError};
+use marker::{ActiveMarker, Marker, RateLimit};
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, net::SocketAddr};
+use std::{
+    collections::{hash_map::Entry, HashMap, HashSet, VecDeque},
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use store::{RoomSave, RoomStore};
+use tokio_tungstenite::tungstenite::protocol::{frame::coding::CloseCode, CloseFrame};
 use tokio_tungstenite::tungstenite::Message;
-use tracing::{trace, warn};
-use user::{AccessLevel, User};
+use tracing::{error, info, trace, warn};
+use user::{AccessLevel, DisconnectReason, Presence, User, UserPresence};
 use uuid::Uuid;
 
+pub mod chat;
 pub mod color;
+pub mod comment;
 pub mod element;
+pub mod history;
+pub mod marker;
+pub mod password;
+pub mod store;
 pub mod user;
 
 pub use color::Color;
 
-#[derive(Default, Debug)]
+/// The current time, in milliseconds since the Unix epoch.
+pub(crate) fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64)
+}
+
+#[derive(Debug)]
 pub struct App {
     rooms: HashMap<String, Room>,
+    /// Where rooms are saved to once everyone has left them.
+    store: Arc<dyn RoomStore>,
+    /// How long an admin who disconnects stays the admin, in case they come
+    /// back.
+    admin_grace: Duration,
 }
 impl App {
-    pub fn new() -> Self {
-        Self::default()
+    pub fn new(store: Arc<dyn RoomStore>, admin_grace: Duration) -> Self {
+        Self {
+            rooms: HashMap::new(),
+            store,
+            admin_grace,
+        }
     }
 
     pub fn get_room(&self, name: &str) -> Option<&Room> {
         self.rooms.get(name)
     }
-    pub fn get_or_insert_room(&mut self, name: String, password: Option<String>) -> &mut Room {
-        self.rooms
-            .entry(name.clone())
-            .or_insert_with(|| Room::new(name, password))
+    /// Returns the room with the given name, loading it from the store if it
+    /// is not currently open.
+    ///
+    /// Returns `None` if the room is neither open nor saved.
+    pub fn get_or_load_room(&mut self, name: &str) -> Option<&mut Room> {
+        if !self.rooms.contains_key(name) {
+            let room = Room::load(name.to_string(), self.store.clone())?;
+            self.rooms.insert(name.to_string(), room);
+        }
+        self.rooms.get_mut(name)
+    }
+    /// Returns the room with the given name, loading it or creating it with
+    /// the given password hash if needed.
+    pub fn get_or_insert_room(&mut self, name: String, password_hash: Option<String>) -> &mut Room {
+        match self.rooms.entry(name.clone()) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(
+                Room::load(name.clone(), self.store.clone())
+                    .unwrap_or_else(|| Room::new(name, password_hash, self.store.clone())),
+            ),
+        }
     }
     pub fn get_room_mut(&mut self, name: &str) -> Option<&mut Room> {
         self.rooms.get_mut(name)
     }
+    /// Closes a room that nobody has joined, without saving it.
+    ///
+    /// This is used when a room was loaded to check a user's password but
+    /// they never got to join it, so nothing in it changed.
+    pub fn unload_room_if_empty(&mut self, name: &str) {
+        if let Entry::Occupied(entry) = self.rooms.entry(name.to_string())
+            && entry.get().users.is_empty()
+        {
+            entry.remove().save_on_drop = false;
+        }
+    }
     /// Disconnect a user from a room.
     ///
     /// Returns `true` if the user was removed from the list of users for the room.
     pub fn disconnect_user(&mut self, room_name: &str, addr: &SocketAddr) -> bool {
+        let admin_grace = self.admin_grace;
         if let Some(room) = self.get_room_mut(room_name) {
-            let removed_user = room.remove_user(addr);
-            // Delete the room if noone is left in it.
+            // Admins stay in the room for a bit in case they're just
+            // refreshing the page.
+            if room.hold_admin(*addr, admin_grace) {
+                return false;
+            }
+
+            let removed_user = room
+                .get_user_from_addr(*addr)
+                .map(|user| user.uuid)
+                .is_some_and(|uuid| room.remove_user(uuid));
+            // Delete the room if noone is left in it. It's saved as it's
+            // dropped.
             if room.users.is_empty() {
-                room.save_to_file();
                 self.rooms.remove(room_name);
             }
             removed_user
@@ -50,58 +121,252 @@ impl App {
             false
         }
     }
+    /// Marks a user whose connection dropped as disconnected, without removing
+    /// them from the room.
+    ///
+    /// They can reconnect with their resume token to pick up where they left
+    /// off. If they don't, they are removed by
+    /// [`App::remove_expired_disconnects`].
+    pub fn connection_lost(&mut self, room_name: &str, addr: &SocketAddr) {
+        let admin_grace = self.admin_grace;
+        if let Some(room) = self.get_room_mut(room_name)
+            && !room.hold_admin(*addr, admin_grace)
+            && let Some(user) = room.get_user_from_addr_mut(*addr)
+        {
+            trace!("User `{}` lost connection to `{room_name}`", user.uuid);
+            user.disconnected_at = Some(Instant::now());
+            user.tx.close_channel();
+        }
+    }
+    /// Removes users who have been disconnected for longer than `grace`
+    /// without resuming their session, and admins who didn't come back before
+    /// their admin status ran out.
+    pub fn remove_expired_disconnects(&mut self, grace: Duration) {
+        let mut empty_rooms = vec![];
+        let now = SystemTime::now();
+        for (room_name, room) in &mut self.rooms {
+            let expired: Vec<_> = room
+                .users
+                .iter()
+                .filter(|u| match u.admin_held_until {
+                    Some(until) => until <= now,
+                    None => u.disconnected_at.is_some_and(|at| at.elapsed() >= grace),
+                })
+                .map(|u| u.uuid)
+                .collect();
+            if expired.is_empty() {
+                continue;
+            }
+            for uuid in expired {
+                trace!("User `{uuid}` didn't reconnect to `{room_name}` in time");
+                room.remove_user(uuid);
+            }
+            if room.users.is_empty() {
+                empty_rooms.push(room_name.clone());
+            }
+        }
+        // Delete the room if noone is left in it. It's saved as it's dropped.
+        for room_name in empty_rooms {
+            self.rooms.remove(&room_name);
+        }
+    }
+    /// Closes a room, disconnecting everyone in it and deleting it from the
+    /// store.
+    pub fn close_room(&mut self, name: &str) {
+        let Some(mut room) = self.rooms.remove(name) else {
+            return;
+        };
+        info!("Closing room `{name}`");
+        room.closed = true;
+        for user in room.users.drain(..) {
+            user.disconnect(DisconnectReason::RoomClosed);
+        }
+        drop(room);
+
+        if let Err(e) = self.store.delete(name) {
+            error!("Failed to delete closed room `{name}`: {e}");
+        }
+    }
+    /// Where rooms are saved to.
+    pub fn store(&self) -> Arc<dyn RoomStore> {
+        self.store.clone()
+    }
+    /// The names of every room that's currently open.
+    pub fn open_room_names(&self) -> HashSet<String> {
+        self.rooms.keys().cloned().collect()
+    }
+    /// Sends everyone the presences that changed since this was last called.
+    pub fn flush_presences(&mut self) {
+        for room in self.rooms.values_mut() {
+            room.flush_presences();
+        }
+    }
+    /// Removes pings and laser trails that have expired.
+    pub fn remove_expired_markers(&mut self) {
+        for room in self.rooms.values_mut() {
+            room.remove_expired_markers();
+        }
+    }
     /// Sends a `ping` to all connected sockets.
     pub fn send_pings(&self) {
         for room in self.rooms.values() {
-            for user in &room.users {
+            for user in room.users.iter().filter(|u| u.disconnected_at.is_none()) {
                 user.ping();
             }
         }
     }
 }
 
+/// Whether a command succeeded. This is included in every response to a
+/// command that had an `id`.
+#[derive(Serialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum CommandStatus {
+    Ok,
+    Error,
+}
+
 #[derive(Serialize)]
 pub struct CommandError {
-    id: Uuid,
+    /// The id of the command that failed, if it had one.
+    id: Option<Uuid>,
+    status: CommandStatus,
     error: commands::ErrorType,
 }
 #[derive(Serialize)]
 pub struct CommandAnnounce {
     id: Option<Uuid>,
+    /// Only included when responding to a command that had an `id`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    status: Option<CommandStatus>,
+    /// The room's sequence number after this announcement. Announcements sent
+    /// to more than one user each increase it by one; responses to a single
+    /// user have the latest one.
+    seq: u64,
     data: commands::AnnounceType,
 }
+/// Acknowledges a command that succeeded but had nothing else to respond
+/// with.
+#[derive(Serialize)]
+pub struct CommandAck {
+    id: Uuid,
+    status: CommandStatus,
+    seq: u64,
+}
+
+/// The most announcements kept for replaying to users who missed them.
+const MAX_EVENT_LOG_LEN: usize = 500;
+
+/// An announcement that was sent to more than one user.
+#[derive(Debug)]
+struct LoggedEvent {
+    seq: u64,
+    /// The canvas it was sent to, or `None` if it was sent to everyone.
+    canvas: Option<u16>,
+    /// The announcement, exactly as it was sent.
+    msg: String,
+}
 
 #[derive(Debug)]
 pub struct Room {
     /// The name of this room.
     name: String,
+    /// Where this room is saved to once everyone leaves.
+    store: Arc<dyn RoomStore>,
     /// All users currently in this room.
     users: Vec<RoomUser>,
     /// All canvases currently in this room.
     canvases: HashMap<u16, RoomCanvas>,
     /// Config info about the room.
     config: RoomConfig,
+    /// Whether the admin closed this room. Closed rooms are not saved.
+    closed: bool,
+    /// Whether the room is saved when it's dropped. Rooms that were only
+    /// loaded to check whether someone can join them haven't changed, and
+    /// saving them would stop them from ever expiring.
+    save_on_drop: bool,
+    /// The sequence number of the last announcement.
+    seq: u64,
+    /// The most recent announcements, so users who missed them can catch up.
+    events: VecDeque<LoggedEvent>,
+    /// The user presenting to everyone following them, if anyone is.
+    presenter: Option<Uuid>,
+    /// The most recent chat messages, oldest first.
+    chat: VecDeque<ChatMessage>,
+    /// Pings and laser trails that haven't expired yet.
+    markers: Vec<ActiveMarker>,
 }
 impl Room {
     /// Creates a new empty room.
-    pub fn new(room_name: String, room_password: Option<String>) -> Room {
+    pub fn new(
+        room_name: String,
+        password_hash: Option<String>,
+        store: Arc<dyn RoomStore>,
+    ) -> Room {
         trace!("Creating room `{room_name}`");
         Room {
             name: room_name,
+            store,
             users: vec![],
             canvases: HashMap::new(),
-            config: RoomConfig::new(room_password),
+            config: RoomConfig::new(password_hash),
+            closed: false,
+            save_on_drop: true,
+            seq: 0,
+            events: VecDeque::new(),
+            presenter: None,
+            chat: VecDeque::new(),
+            markers: vec![],
         }
     }
 
+    /// Loads a previously saved room from the store.
+    ///
+    /// Returns `None` if the room was never saved or couldn't be loaded.
+    pub fn load(room_name: String, store: Arc<dyn RoomStore>) -> Option<Room> {
+        let save = match store.load(&room_name) {
+            Ok(save) => save?,
+            Err(e) => {
+                error!("Failed to load room `{room_name}`: {e}");
+                return None;
+            }
+        };
+
+        info!("Loaded room `{room_name}`");
+        let mut canvases = save.canvases;
+        // Nobody is in the room yet, so nothing can be selected.
+        for canvas in canvases.values_mut() {
+            for el in &mut canvas.elements {
+                el.selected_by = None;
+            }
+        }
+        Some(Room {
+            name: room_name,
+            store,
+            users: vec![],
+            canvases,
+            config: save.config,
+            closed: false,
+            save_on_drop: true,
+            seq: 0,
+            events: VecDeque::new(),
+            presenter: None,
+            chat: save.chat,
+            markers: vec![],
+        })
+    }
+
     pub fn get_config(&self) -> &RoomConfig {
         &self.config
     }
-    pub fn is_password_correct(&self, guessed_password: Option<&str>) -> bool {
-        self.config.password.as_deref() == guessed_password
+    pub fn get_config_mut(&mut self) -> &mut RoomConfig {
+        &mut self.config
     }
-    pub fn requires_password(&self) -> bool {
-        self.config.password.is_some()
+    /// The hash of the room's password, if it has one.
+    ///
+    /// Check a password against it with [`password::verify`].
+    pub fn password_hash(&self) -> Option<&str> {
+        self.config.password_hash.as_deref()
     }
 
     /// Returns the given canvas.
@@ -112,6 +377,50 @@ impl Room {
         self.canvases.get(&id)
     }
 
+    /// Returns a copy of every canvas in the room, for exporting.
+    pub fn export_canvases(&self) -> HashMap<u16, RoomCanvas> {
+        let mut canvases = self.canvases.clone();
+        for canvas in canvases.values_mut() {
+            for el in &mut canvas.elements {
+                el.selected_by = None;
+            }
+        }
+        canvases
+    }
+    /// Replaces every canvas in the room, such as when importing a room.
+    ///
+    /// Everyone is sent the new state of the canvas they are on.
+    pub fn replace_canvases(&mut self, mut canvases: HashMap<u16, RoomCanvas>) {
+        for canvas in canvases.values_mut() {
+            for el in &mut canvas.elements {
+                el.selected_by = None;
+            }
+        }
+        self.canvases = canvases;
+
+        let user_canvases: HashSet<_> = self.users.iter().map(|u| u.canvas).collect();
+        for canvas in user_canvases {
+            let (elements, threads) = self
+                .canvases
+                .get(&canvas)
+                .map(|canvas| (canvas.elements.clone(), canvas.threads.clone()))
+                .unwrap_or_default();
+            self.announce_to_canvas(
+                canvas,
+                AnnounceType::CanvasResponse {
+                    canvas,
+                    elements,
+                    threads,
+                },
+                None,
+            );
+        }
+    }
+
+    /// Returns every user in the room.
+    pub fn get_users(&self) -> impl Iterator<Item = &RoomUser> {
+        self.users.iter()
+    }
     /// Retrieves a user.
     pub fn get_user(&self, user: Uuid) -> Option<&RoomUser> {
         self.users.iter().find(|u| u.uuid == user)
@@ -121,12 +430,19 @@ impl Room {
         self.users.iter_mut().find(|u| u.uuid == user)
     }
     /// Retrieves a user from their socket address.
+    ///
+    /// Users who are disconnected are skipped, as their address may have
+    /// been reused by someone else.
     pub fn get_user_from_addr(&self, user: SocketAddr) -> Option<&RoomUser> {
-        self.users.iter().find(|u| u.addr == user)
+        self.users
+            .iter()
+            .find(|u| u.addr == user && u.disconnected_at.is_none())
     }
     /// Retrieves a user mutably from their socket address.
     pub fn get_user_from_addr_mut(&mut self, user: SocketAddr) -> Option<&mut RoomUser> {
-        self.users.iter_mut().find(|u| u.addr == user)
+        self.users
+            .iter_mut()
+            .find(|u| u.addr == user && u.disconnected_at.is_none())
     }
     /// Returns the current admin of the room
     pub fn get_admin(&self) -> Option<&RoomUser> {
@@ -134,6 +450,58 @@ impl Room {
             .iter()
             .find(|x| x.access_level == AccessLevel::Admin)
     }
+    /// Removes a user from the room straight away, telling them why.
+    ///
+    /// Returns whether the user was found.
+    pub fn kick_user(&mut self, user: Uuid, reason: DisconnectReason) -> bool {
+        let Some(found_user) = self.get_user(user) else {
+            return false;
+        };
+        trace!(
+            "Removing `{}` [{user}] from `{}`: {reason:?}",
+            found_user.username,
+            self.name
+        );
+        found_user.disconnect(reason);
+        self.remove_user(user)
+    }
+    /// Bans a user from the room and removes them from it. They can't resume
+    /// their session as the same user, and if `ban_ip` is set they can't
+    /// rejoin at all from the same IP address. Without it, they can rejoin
+    /// straight away as someone new.
+    ///
+    /// Returns whether the user was found.
+    pub fn ban_user(&mut self, user: Uuid, ban_ip: bool, by: Uuid) -> bool {
+        let Some(found_user) = self.get_user(user) else {
+            return false;
+        };
+        self.config.bans.push(Ban {
+            user,
+            username: found_user.username.clone(),
+            resume_token: found_user.resume_token.clone(),
+            ip: ban_ip.then_some(found_user.ip),
+        });
+        self.kick_user(user, DisconnectReason::Banned { by })
+    }
+    /// Whether someone joining from `ip`, resuming their session with
+    /// `resume_token` if given, is banned from the room.
+    pub fn is_banned(&self, ip: IpAddr, resume_token: Option<&str>) -> bool {
+        let user = resume_token.and_then(|token| {
+            self.users
+                .iter()
+                .find(|u| u.resume_token == token)
+                .map(|u| u.uuid)
+        });
+        self.config.is_banned(ip, user, resume_token)
+    }
+    /// Lifts any bans on a user.
+    ///
+    /// Returns whether they were banned.
+    pub fn unban_user(&mut self, user: Uuid) -> bool {
+        let len = self.config.bans.len();
+        self.config.bans.retain(|ban| ban.user != user);
+        self.config.bans.len() != len
+    }
     /// Adds a user to the room.
     pub fn add_user(&mut self, user: RoomUser) {
         trace!(
@@ -153,16 +521,106 @@ impl Room {
             None,
         );
 
-        let elements = self.get_or_create_canvas(user.canvas).elements.clone();
+        let addr = user.addr;
         self.users.push(user);
-        let user = self.users.last().unwrap();
+        self.respond_on_join(addr);
+    }
+    /// Reconnects a user who lost their connection, if the resume token
+    /// belongs to someone in the room.
+    ///
+    /// They keep the same UUID, access level, canvas and selections, and
+    /// nobody else is told they left. They're given a new resume token.
+    /// Returns `false` if nobody has the token.
+    pub fn resume_user(
+        &mut self,
+        resume_token: &str,
+        addr: SocketAddr,
+        ip: IpAddr,
+        tx: &UnboundedSender<Message>,
+    ) -> bool {
+        let Some(user) = self
+            .users
+            .iter_mut()
+            .find(|u| u.resume_token == resume_token)
+        else {
+            return false;
+        };
+        trace!(
+            "User `{}` [{}] resumed their session in `{}` from [{addr}]",
+            user.username,
+            user.uuid,
+            self.name
+        );
+        // Their old connection may not have noticed it dropped yet.
+        user.tx.close_channel();
+        user.addr = addr;
+        user.ip = ip;
+        user.tx = tx.clone();
+        user.disconnected_at = None;
+        user.resume_token = user::new_resume_token();
+        let was_held = user.admin_held_until.take().is_some();
+        let user: User = user.clone().into();
+        if was_held {
+            self.announce_to_all(commands::AnnounceType::UserChange { user }, None);
+        }
+        self.respond_on_join(addr);
+        true
+    }
+    /// If the user is the admin, keeps them in the room as admin for `grace`
+    /// after they disconnect, so they don't lose control of the room by
+    /// refreshing the page. Everyone is told until when.
+    ///
+    /// Returns whether the admin is being held. Admins on their own aren't
+    /// held, as there's nobody to hand the room to anyway.
+    fn hold_admin(&mut self, addr: SocketAddr, grace: Duration) -> bool {
+        let has_others = self
+            .users
+            .iter()
+            .any(|u| u.addr != addr && u.disconnected_at.is_none());
+        let Some(user) = self.get_user_from_addr_mut(addr) else {
+            return false;
+        };
+        if user.access_level != AccessLevel::Admin || grace.is_zero() || !has_others {
+            return false;
+        }
+
+        trace!("Holding admin for `{}` for {grace:?}", user.uuid);
+        user.disconnected_at = Some(Instant::now());
+        user.admin_held_until = Some(SystemTime::now() + grace);
+        user.tx.close_channel();
+        let user = user.clone().into();
+        self.announce_to_all(commands::AnnounceType::UserChange { user }, None);
+        true
+    }
+    /// Responds to a user who just joined with everything they need to know
+    /// about the room.
+    fn respond_on_join(&mut self, addr: SocketAddr) {
+        let Some(canvas) = self.get_user_from_addr(addr).map(|u| u.canvas) else {
+            return;
+        };
+        let room_canvas = self.get_or_create_canvas(canvas);
+        let elements = room_canvas.elements.clone();
+        let threads = room_canvas.threads.clone();
+        let user = self.get_user_from_addr(addr).unwrap();
+        let presences = self
+            .users
+            .iter()
+            .filter(|u| u.canvas == canvas && u.uuid != user.uuid)
+            .filter_map(RoomUser::user_presence)
+            .collect();
 
         self.respond_to_user(
-            user.addr,
+            addr,
             commands::AnnounceType::OnJoin {
                 user: user.clone().into(),
                 users: self.users.iter().map(|x| x.clone().into()).collect(),
                 elements,
+                threads,
+                resume_token: user.resume_token.clone(),
+                config: self.config.public(),
+                presences,
+                presenter: self.presenter,
+                chat: self.chat.iter().cloned().collect(),
             },
             None,
         )
@@ -170,10 +628,10 @@ impl Room {
     /// Remove a user from the room.
     /// Use [`App::disconnect_user`] for this, as it will also remove the room
     /// if noone is left in it.
-    fn remove_user(&mut self, addr: &SocketAddr) -> bool {
+    fn remove_user(&mut self, uuid: Uuid) -> bool {
         let mut removed_user = None;
         self.users.retain(|u| {
-            if &u.addr == addr {
+            if u.uuid == uuid {
                 removed_user = Some(u.clone());
                 false
             } else {
@@ -207,13 +665,22 @@ impl Room {
             None,
         );
 
+        // Nobody can follow someone who isn't here.
+        if self.presenter == Some(removed_user.uuid) {
+            self.stop_presentation();
+        }
+
         // Check we have an admin in the lobby.
         if removed_user.access_level == AccessLevel::Admin {
-            // Make the first editor the admin, else the first user
+            // Make the first editor the admin, else the first user. Prefer
+            // people who are still connected.
+            let is_connected = |u: &&RoomUser| u.disconnected_at.is_none();
             let to_admin = self
                 .users
                 .iter()
+                .filter(is_connected)
                 .find(|u| u.access_level == AccessLevel::Edit)
+                .or_else(|| self.users.iter().find(is_connected))
                 .unwrap_or_else(|| self.users.first().unwrap());
             self.change_access_level(to_admin.uuid, AccessLevel::Admin);
         }
@@ -222,15 +689,24 @@ impl Room {
     }
 
     /// Send an announcement to everyone but one user.
+    ///
+    /// If the command had an `id`, the sender always gets exactly one
+    /// response with it, saying whether the command succeeded.
     pub fn announce(
-        &self,
+        &mut self,
         announcement: Result<AnnounceTo, ErrorType>,
         sender: SocketAddr,
         id: Option<Uuid>,
     ) {
         match announcement {
-            Ok(AnnounceTo::All(t)) => self.announce_to_all(t, id),
-            Ok(AnnounceTo::Canvas(t, c)) => self.announce_to_canvas(c, t, id),
+            Ok(AnnounceTo::All(t)) => {
+                self.announce_to_all(t, None);
+                self.acknowledge(sender, id);
+            }
+            Ok(AnnounceTo::Canvas(t, c)) => {
+                self.announce_to_canvas(c, t, None);
+                self.acknowledge(sender, id);
+            }
             Ok(AnnounceTo::Respond(t)) => self.respond_to_user(sender, t, id),
             Ok(AnnounceTo::ResponseAndAnnounce { respond, announce }) => {
                 self.respond_and_announce(announce, sender, respond, id)
@@ -240,42 +716,61 @@ impl Room {
                 announce,
                 canvas,
             }) => self.respond_and_announce_to_canvas(canvas, announce, sender, respond, id),
-            Ok(AnnounceTo::None) => {}
+            Ok(AnnounceTo::None) => self.acknowledge(sender, id),
             Err(error) => self.respond_error(sender, error, id),
         }
     }
-    pub fn announce_to_all(&self, data: AnnounceType, id: Option<Uuid>) {
-        let msg = serde_json::to_string(&CommandAnnounce { id, data })
-            .expect("failed to serialize announcement");
+    pub fn announce_to_all(&mut self, data: AnnounceType, id: Option<Uuid>) {
+        let msg = self.log_event(None, data, id);
         for user in &self.users {
             user.send_str(&msg);
         }
     }
-    pub fn announce_to_canvas(&self, canvas: u16, data: AnnounceType, id: Option<Uuid>) {
-        let msg = serde_json::to_string(&CommandAnnounce { id, data })
-            .expect("failed to serialize announcement");
+    pub fn announce_to_canvas(&mut self, canvas: u16, data: AnnounceType, id: Option<Uuid>) {
+        let msg = self.log_event(Some(canvas), data, id);
+        for user in self.users.iter().filter(|u| u.canvas == canvas) {
+            user.send_str(&msg);
+        }
+    }
+    /// Sends an announcement to all users on a canvas without giving it a
+    /// sequence number or keeping it to be replayed. This is for frequent
+    /// updates that are useless once they're out of date.
+    pub fn announce_to_canvas_ephemeral(&self, canvas: u16, data: AnnounceType) {
+        let msg = serde_json::to_string(&CommandAnnounce {
+            id: None,
+            status: None,
+            seq: self.seq,
+            data,
+        })
+        .expect("failed to serialize announcement");
         for user in self.users.iter().filter(|u| u.canvas == canvas) {
             user.send_str(&msg);
         }
     }
     pub fn respond_to_user(&self, sender: SocketAddr, data: AnnounceType, id: Option<Uuid>) {
         if let Some(user) = self.get_user_from_addr(sender) {
-            user.send(&CommandAnnounce { id, data })
+            user.send(&CommandAnnounce {
+                id,
+                status: id.map(|_| CommandStatus::Ok),
+                seq: self.seq,
+                data,
+            })
         }
     }
     pub fn respond_and_announce(
-        &self,
+        &mut self,
         announce: AnnounceType,
         sender: SocketAddr,
         respond: AnnounceType,
         id: Option<Uuid>,
     ) {
-        let msg = serde_json::to_string(&CommandAnnounce {
-            id: None,
-            data: announce,
-        })
-        .expect("failed to serialize announcement");
-        let r_msg = CommandAnnounce { id, data: respond };
+        let msg = self.log_event(None, announce, None);
+        let r_msg = CommandAnnounce {
+            id,
+            status: id.map(|_| CommandStatus::Ok),
+            seq: self.seq,
+            data: respond,
+        };
         for user in &self.users {
             if user.addr == sender {
                 user.send(&r_msg);
@@ -285,19 +780,20 @@ impl Room {
         }
     }
     pub fn respond_and_announce_to_canvas(
-        &self,
+        &mut self,
         canvas: u16,
         announce: AnnounceType,
         sender: SocketAddr,
         respond: AnnounceType,
         id: Option<Uuid>,
     ) {
-        let msg = serde_json::to_string(&CommandAnnounce {
-            id: None,
-            data: announce,
-        })
-        .expect("failed to serialize announcement");
-        let r_msg = CommandAnnounce { id, data: respond };
+        let msg = self.log_event(Some(canvas), announce, None);
+        let r_msg = CommandAnnounce {
+            id,
+            status: id.map(|_| CommandStatus::Ok),
+            seq: self.seq,
+            data: respond,
+        };
         for user in &self.users {
             if user.addr == sender {
                 user.send(&r_msg);
@@ -306,11 +802,68 @@ impl Room {
             }
         }
     }
-    pub fn respond_error(&self, sender: SocketAddr, error: ErrorType, id: Option<Uuid>) {
+    /// Gives an announcement the next sequence number and keeps it so it can
+    /// be replayed, returning it serialized.
+    fn log_event(&mut self, canvas: Option<u16>, data: AnnounceType, id: Option<Uuid>) -> String {
+        self.seq += 1;
+        let msg = serde_json::to_string(&CommandAnnounce {
+            id,
+            status: None,
+            seq: self.seq,
+            data,
+        })
+        .expect("failed to serialize announcement");
+        self.events.push_back(LoggedEvent {
+            seq: self.seq,
+            canvas,
+            msg: msg.clone(),
+        });
+        if self.events.len() > MAX_EVENT_LOG_LEN {
+            self.events.pop_front();
+        }
+        msg
+    }
+    /// Returns the announcements a user on the given canvas would have been
+    /// sent after `seq`, in order.
+    ///
+    /// Returns `None` if some of them are no longer kept, in which case the
+    /// user needs to fetch everything again.
+    pub fn events_after(&self, seq: u64, canvas: u16) -> Option<Vec<&str>> {
+        if seq > self.seq {
+            return None;
+        }
+        if seq < self.seq && self.events.front().is_none_or(|ev| ev.seq > seq + 1) {
+            return None;
+        }
+        Some(
+            self.events
+                .iter()
+                .filter(|ev| ev.seq > seq && ev.canvas.is_none_or(|c| c == canvas))
+                .map(|ev| ev.msg.as_str())
+                .collect(),
+        )
+    }
+    /// Tells the sender their command succeeded, if it had an `id`.
+    pub fn acknowledge(&self, sender: SocketAddr, id: Option<Uuid>) {
         if let Some(id) = id
             && let Some(user) = self.get_user_from_addr(sender)
         {
-            user.send(&CommandError { id, error });
+            user.send(&CommandAck {
+                id,
+                status: CommandStatus::Ok,
+                seq: self.seq,
+            });
+        }
+    }
+    /// Tells the sender their command failed. This is sent even if the
+    /// command didn't have an `id`.
+    pub fn respond_error(&self, sender: SocketAddr, error: ErrorType, id: Option<Uuid>) {
+        if let Some(user) = self.get_user_from_addr(sender) {
+            user.send(&CommandError {
+                id,
+                status: CommandStatus::Error,
+                error,
+            });
         }
     }
 
@@ -359,9 +912,158 @@ impl Room {
         true
     }
 
+    /// Updates where a user is looking. This is sent to everyone else on
+    /// their canvas by [`Room::flush_presences`].
+    pub fn set_presence(&mut self, addr: SocketAddr, presence: Presence) -> Option<()> {
+        let user = self.get_user_from_addr_mut(addr)?;
+        user.presence = Some(presence);
+        user.presence_changed = true;
+        Some(())
+    }
+    /// Sends the presences that changed since this was last called to
+    /// everyone on the same canvas, in one announcement per canvas.
+    pub fn flush_presences(&mut self) {
+        let mut changed: HashMap<u16, Vec<UserPresence>> = HashMap::new();
+        for user in self.users.iter_mut().filter(|u| u.presence_changed) {
+            user.presence_changed = false;
+            if let Some(presence) = user.user_presence() {
+                changed.entry(user.canvas).or_default().push(presence);
+            }
+        }
+        for (canvas, presences) in changed {
+            self.announce_to_canvas_ephemeral(canvas, AnnounceType::Presence { presences });
+        }
+    }
+
+    /// The most recent chat messages, oldest first.
+    pub fn get_chat(&self) -> &VecDeque<ChatMessage> {
+        &self.chat
+    }
+    /// Adds a message to the chat, forgetting the oldest if there are too
+    /// many.
+    pub fn add_chat_message(&mut self, message: ChatMessage) {
+        self.chat.push_back(message);
+        while self.chat.len() > chat::MAX_CHAT_HISTORY_LEN {
+            self.chat.pop_front();
+        }
+    }
+    /// Deletes a message from the chat.
+    ///
+    /// Returns whether the message was found.
+    pub fn delete_chat_message(&mut self, message: Uuid) -> bool {
+        let len = self.chat.len();
+        self.chat.retain(|m| m.uuid != message);
+        self.chat.len() != len
+    }
+
+    /// Puts a marker on a canvas until `ttl` passes, announcing it to
+    /// everyone on the canvas.
+    pub fn add_marker(&mut self, canvas: u16, ttl: Duration, mut marker: Marker) {
+        marker.expires_at = unix_millis() + ttl.as_millis() as u64;
+        self.announce_to_canvas_ephemeral(
+            canvas,
+            AnnounceType::MarkerAdded {
+                marker: marker.clone(),
+            },
+        );
+        self.markers.push(ActiveMarker {
+            canvas,
+            expires: Instant::now() + ttl,
+            marker,
+        });
+    }
+    /// Removes markers that have expired, telling everyone on their canvas.
+    pub fn remove_expired_markers(&mut self) {
+        let now = Instant::now();
+        let mut expired: HashMap<u16, Vec<Uuid>> = HashMap::new();
+        self.markers.retain(|m| {
+            if m.expires > now {
+                return true;
+            }
+            expired.entry(m.canvas).or_default().push(m.marker.uuid);
+            false
+        });
+        for (canvas, markers) in expired {
+            self.announce_to_canvas_ephemeral(canvas, AnnounceType::MarkersExpired { markers });
+        }
+    }
+
+    /// The user presenting, if anyone is.
+    pub fn get_presenter(&self) -> Option<&RoomUser> {
+        self.presenter
+            .and_then(|presenter| self.get_user(presenter))
+    }
+    /// Starts a presentation. Everyone else starts following the presenter
+    /// and is moved to their canvas.
+    pub fn start_presentation(&mut self, presenter: Uuid) {
+        self.presenter = Some(presenter);
+        for user in &mut self.users {
+            user.following = user.uuid != presenter;
+        }
+        self.announce_to_all(AnnounceType::PresentationStarted { presenter }, None);
+        self.move_followers();
+    }
+    /// Stops the presentation, if there is one. Nobody is following anyone
+    /// afterwards.
+    ///
+    /// Returns whether there was a presentation.
+    pub fn stop_presentation(&mut self) -> bool {
+        let Some(presenter) = self.presenter.take() else {
+            return false;
+        };
+        for user in &mut self.users {
+            user.following = false;
+        }
+        self.announce_to_all(AnnounceType::PresentationStopped { presenter }, None);
+        true
+    }
+    /// Moves everyone following the presenter to the presenter's canvas.
+    pub fn move_followers(&mut self) {
+        let Some(canvas) = self.get_presenter().map(|p| p.canvas) else {
+            return;
+        };
+        let followers: Vec<Uuid> = self
+            .users
+            .iter()
+            .filter(|u| u.following && u.canvas != canvas)
+            .map(|u| u.uuid)
+            .collect();
+        for follower in followers {
+            self.move_to_canvas(follower, canvas);
+            if let Some(user) = self.get_user(follower) {
+                let user = user.clone().into();
+                self.announce_to_all(AnnounceType::UserChange { user }, None);
+            }
+        }
+    }
+    /// Switches a user to another canvas and sends them what's on it.
+    pub fn move_to_canvas(&mut self, user: Uuid, canvas: u16) {
+        let Some(addr) = self.get_user(user).map(|u| u.addr) else {
+            return;
+        };
+        if self.switch_canvas(addr, canvas).is_none() {
+            return;
+        }
+        let room_canvas = self.get_or_create_canvas(canvas);
+        let elements = room_canvas.elements.clone();
+        let threads = room_canvas.threads.clone();
+        self.respond_to_user(
+            addr,
+            AnnounceType::CanvasResponse {
+                canvas,
+                elements,
+                threads,
+            },
+            None,
+        );
+    }
+
     pub fn switch_canvas(&mut self, addr: SocketAddr, canvas: u16) -> Option<()> {
         let user = self.get_user_from_addr_mut(addr)?;
         user.canvas = canvas;
+        // Where they were looking on the old canvas means nothing on this one.
+        user.presence = None;
+        user.presence_changed = false;
         let uuid = user.uuid;
 
         // Deselect all elements from this user.
@@ -375,26 +1077,69 @@ impl Room {
         Some(())
     }
 
-    pub fn save_to_file(&self) {}
+    /// Saves the room's config and canvases to the store so it can be
+    /// reloaded the next time someone joins it.
+    pub fn save(&self) {
+        let save = RoomSave {
+            config: self.config.clone(),
+            canvases: self.canvases.clone(),
+            chat: self.chat.clone(),
+        };
+        match self.store.save(&self.name, &save) {
+            Ok(()) => trace!("Saved room `{}`", self.name),
+            Err(e) => error!("Failed to save room `{}`: {e}", self.name),
+        }
+    }
 }
 impl Drop for Room {
     fn drop(&mut self) {
         trace!("Deleting room `{}`", self.name);
-        self.save_to_file()
+        if !self.closed && self.save_on_drop {
+            self.save()
+        }
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct RoomUser {
     pub(crate) addr: SocketAddr,
+    /// The IP address of the user. This is different from `addr` when the
+    /// server is behind a proxy.
+    pub(crate) ip: IpAddr,
     pub(crate) tx: UnboundedSender<Message>,
     pub(crate) uuid: Uuid,
     pub(crate) username: String,
     pub(crate) color: Color,
     pub(crate) canvas: u16,
     pub(crate) access_level: user::AccessLevel,
+    /// The secret the user can reconnect with to resume their session.
+    pub(crate) resume_token: String,
+    /// When the user's connection dropped, if it has. They stay in the room
+    /// for a while in case they reconnect.
+    pub(crate) disconnected_at: Option<Instant>,
+    /// If this is the admin and they disconnected, when they stop being the
+    /// admin if they haven't come back.
+    pub(crate) admin_held_until: Option<SystemTime>,
+    /// Where the user is looking on their canvas, if they've said.
+    pub(crate) presence: Option<Presence>,
+    /// Whether `presence` changed since it was last sent to everyone.
+    pub(crate) presence_changed: bool,
+    /// Whether the user is following the presenter to whichever canvas
+    /// they're on.
+    pub(crate) following: bool,
+    /// How often the user has placed pings recently.
+    pub(crate) ping_limit: RateLimit,
+    /// How often the user has sent laser trails recently.
+    pub(crate) laser_limit: RateLimit,
 }
 impl RoomUser {
+    fn user_presence(&self) -> Option<UserPresence> {
+        Some(UserPresence {
+            user: self.uuid,
+            presence: self.presence.clone()?,
+        })
+    }
+
     /// Sends a personalized message to this user.
     ///
     /// Always prefer [`Room::announce`] if possible.
@@ -402,13 +1147,29 @@ impl RoomUser {
         self.send_str(&serde_json::to_string(msg).expect("failed to serialize message"))
     }
 
-    fn send_str(&self, msg: &str) {
+    pub(crate) fn send_str(&self, msg: &str) {
+        if self.disconnected_at.is_some() {
+            return;
+        }
         let _ = self
             .tx
             .unbounded_send(Message::text(msg))
             .inspect_err(|e| warn!("Failed to send to user: {e:#?}"));
     }
 
+    /// Disconnects this user, telling them why.
+    pub fn disconnect(&self, reason: DisconnectReason) {
+        let reason_json = serde_json::to_string(&reason).expect("failed to serialize reason");
+        let _ = self
+            .tx
+            .unbounded_send(Message::Close(Some(CloseFrame {
+                code: CloseCode::Library(reason.close_code()),
+                reason: reason_json.into(),
+            })))
+            .inspect_err(|e| warn!("Failed to disconnect user: {e:#?}"));
+        self.tx.close_channel();
+    }
+
     /// Sends a `ping` to a user.
     fn ping(&self) {
         let _ = self
@@ -418,9 +1179,15 @@ impl RoomUser {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, Default)]
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
 pub struct RoomCanvas {
     pub elements: Vec<element::Element>,
+    /// Comment threads about elements on this canvas.
+    pub threads: Vec<Thread>,
+    /// Changes made to this canvas that can be undone. This is only kept
+    /// while the room is open.
+    #[serde(skip)]
+    pub history: History,
 }
 impl RoomCanvas {
     pub fn get_element(&self, uuid: &Uuid) -> Option<&element::Element> {
@@ -432,27 +1199,109 @@ impl RoomCanvas {
     pub fn add_element(&mut self, element: Element) {
         self.elements.push(element)
     }
-    pub fn delete_element(&mut self, uuid: &Uuid) {
+    /// Deletes an element, archiving any threads about it.
+    ///
+    /// Returns the threads that were archived.
+    pub fn delete_element(&mut self, uuid: &Uuid) -> Vec<Thread> {
         self.elements.retain(|el| &el.uuid != uuid);
+        self.set_threads_archived(uuid, true)
+    }
+    pub fn get_thread_mut(&mut self, uuid: &Uuid) -> Option<&mut Thread> {
+        self.threads.iter_mut().find(|t| &t.uuid == uuid)
+    }
+    /// Archives or restores every thread about an element.
+    ///
+    /// Returns the threads that changed.
+    fn set_threads_archived(&mut self, element: &Uuid, archived: bool) -> Vec<Thread> {
+        let mut changed = vec![];
+        for thread in &mut self.threads {
+            if &thread.element == element && thread.archived != archived {
+                thread.archived = archived;
+                changed.push(thread.clone());
+            }
+        }
+        changed
+    }
+    /// Undoes the last change `user` made on this canvas.
+    pub fn undo(&mut self, user: Uuid) -> Result<AppliedChanges, HistoryError> {
+        let applied = self.history.undo(user, &mut self.elements)?;
+        Ok(self.update_threads(applied))
+    }
+    /// Redoes the last change `user` undid on this canvas.
+    pub fn redo(&mut self, user: Uuid) -> Result<AppliedChanges, HistoryError> {
+        let applied = self.history.redo(user, &mut self.elements)?;
+        Ok(self.update_threads(applied))
+    }
+    /// Archives threads about elements that undoing or redoing deleted, and
+    /// restores them for elements it brought back.
+    fn update_threads(&mut self, mut applied: AppliedChanges) -> AppliedChanges {
+        for uuid in &applied.deleted_elements {
+            let archived = self.set_threads_archived(uuid, true);
+            applied.threads.extend(archived);
+        }
+        for el in &applied.elements {
+            let restored = self.set_threads_archived(&el.uuid, false);
+            applied.threads.extend(restored);
+        }
+        applied
     }
 }
 
-#[derive(Serialize, Deserialize, Default, Debug)]
+#[derive(Serialize, Deserialize, Default, Debug, Clone)]
 pub struct RoomConfig {
     /// Whether users who just joined the room should be made editors by
     /// default, or should be in view-only mode.
     pub(crate) new_users_default_editor: bool,
-    /// The password for the server.
-    pub(crate) password: Option<String>,
+    /// The hash of the room's password, made by [`password::hash`].
+    pub(crate) password_hash: Option<String>,
+    /// Whether only the admin can export the room's canvases, or if anyone
+    /// can.
+    pub(crate) export_admin_only: bool,
+    /// Users who can't join the room.
+    pub(crate) bans: Vec<Ban>,
+    /// Whether view-only users can send chat messages and comment on
+    /// elements.
+    pub(crate) view_only_chat: bool,
 }
 impl RoomConfig {
-    pub fn new(password: Option<String>) -> RoomConfig {
+    pub fn new(password_hash: Option<String>) -> RoomConfig {
         RoomConfig {
-            password,
+            password_hash,
             ..Default::default()
         }
     }
 
+    /// Whether someone joining from `ip`, resuming the session of `user` with
+    /// `resume_token` if given, is banned from the room.
+    pub fn is_banned(&self, ip: IpAddr, user: Option<Uuid>, resume_token: Option<&str>) -> bool {
+        self.bans.iter().any(|ban| {
+            ban.ip == Some(ip)
+                || Some(ban.user) == user
+                || Some(ban.resume_token.as_str()) == resume_token
+        })
+    }
+
+    /// Whether a user with the given access level can send chat messages and
+    /// comment on elements.
+    pub fn can_chat(&self, access_level: AccessLevel) -> bool {
+        self.view_only_chat || access_level != AccessLevel::View
+    }
+
+    /// Whether a user with the given access level can export the room.
+    pub fn can_export(&self, access_level: AccessLevel) -> bool {
+        !self.export_admin_only || access_level == AccessLevel::Admin
+    }
+
+    /// The settings that everyone in the room can see.
+    pub fn public(&self) -> PublicRoomConfig {
+        PublicRoomConfig {
+            new_users_default_editor: self.new_users_default_editor,
+            requires_password: self.password_hash.is_some(),
+            export_admin_only: self.export_admin_only,
+            view_only_chat: self.view_only_chat,
+        }
+    }
+
     pub fn get_default_access_level(&self) -> AccessLevel {
         if self.new_users_default_editor {
             AccessLevel::Edit
@@ -461,3 +1310,26 @@ impl RoomConfig {
         }
     }
 }
+
+/// The parts of a [`RoomConfig`] that are sent to users. This leaves out
+/// anything secret, like the password.
+#[derive(Serialize, Debug, Clone)]
+pub struct PublicRoomConfig {
+    new_users_default_editor: bool,
+    requires_password: bool,
+    export_admin_only: bool,
+    view_only_chat: bool,
+}
+
+/// A user who was banned from a room.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Ban {
+    /// The UUID the user had when they were banned, so they can't resume a
+    /// session as that user.
+    pub(crate) user: Uuid,
+    pub(crate) username: String,
+    /// The user's resume token, so they can't resume their session.
+    pub(crate) resume_token: String,
+    /// The IP address the user connected from, if that was banned too.
+    pub(crate) ip: Option<IpAddr>,
+}
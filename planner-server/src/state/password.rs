@@ -0,0 +1,33 @@
+//! Hashing of room passwords.
+//!
+//! Passwords are stored as Argon2 hashes in the PHC string format, which
+//! includes the salt and parameters used. Both hashing and verifying are
+//! deliberately slow, so avoid doing them while holding the [`App`] lock.
+//!
+//! [`App`]: super::App
+
+use argon2::{
+    password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use rand::rngs::OsRng;
+
+/// Hashes a password with a random salt.
+pub fn hash(password: &str) -> String {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .expect("hashing with the default parameters doesn't fail")
+        .to_string()
+}
+
+/// Checks a password against a hash made by [`hash`]. The comparison is done
+/// in constant time.
+pub fn verify(hash: &str, password: &str) -> bool {
+    let Ok(hash) = PasswordHash::new(hash) else {
+        return false;
+    };
+    Argon2::default()
+        .verify_password(password.as_bytes(), &hash)
+        .is_ok()
+}
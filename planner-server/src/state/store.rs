@@ -0,0 +1,126 @@
+use super::{chat::ChatMessage, RoomCanvas, RoomConfig};
+use serde::{Deserialize, Serialize};
+use snapshot::SnapshotError;
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    fmt::Display,
+    io,
+    time::{Duration, SystemTime},
+};
+use tracing::error;
+
+mod file;
+mod memory;
+pub mod snapshot;
+mod sqlite;
+
+pub use file::FileStore;
+pub use memory::MemoryStore;
+pub use sqlite::SqliteStore;
+
+/// Somewhere rooms can be saved to once everyone leaves them, and loaded
+/// back from when someone rejoins.
+pub trait RoomStore: std::fmt::Debug + Send + Sync {
+    /// Loads the room with the given name.
+    ///
+    /// Returns `Ok(None)` if the room was never saved.
+    fn load(&self, name: &str) -> Result<Option<RoomSave>, StoreError>;
+    /// Saves a room, replacing whatever was saved under that name before.
+    fn save(&self, name: &str, room: &RoomSave) -> Result<(), StoreError>;
+    /// Lists every saved room.
+    fn list(&self) -> Result<Vec<StoredRoom>, StoreError>;
+    /// Deletes a saved room.
+    ///
+    /// Returns `true` if there was a room to delete.
+    fn delete(&self, name: &str) -> Result<bool, StoreError>;
+}
+
+/// Deletes saved rooms that nobody has been in for longer than `retention`.
+///
+/// Rooms are aged from when they were last saved, which happens when the last
+/// person leaves. Rooms in `open_rooms` are never deleted. This reads and
+/// deletes files, so shouldn't be run on an async task.
+/// Returns the names of the rooms that were deleted.
+pub fn delete_expired_rooms(
+    store: &dyn RoomStore,
+    open_rooms: &HashSet<String>,
+    retention: Duration,
+) -> Vec<String> {
+    let stored_rooms = match store.list() {
+        Ok(stored_rooms) => stored_rooms,
+        Err(e) => {
+            error!("Failed to list saved rooms: {e}");
+            return vec![];
+        }
+    };
+
+    let mut deleted = vec![];
+    for stored_room in stored_rooms {
+        if open_rooms.contains(&stored_room.name) {
+            continue;
+        }
+        let expired = SystemTime::now()
+            .duration_since(stored_room.saved_at)
+            .is_ok_and(|age| age >= retention);
+        if !expired {
+            continue;
+        }
+
+        match store.delete(&stored_room.name) {
+            Ok(_) => deleted.push(stored_room.name),
+            Err(e) => error!("Failed to delete expired room `{}`: {e}", stored_room.name),
+        }
+    }
+    deleted
+}
+
+/// The data of a [`Room`] that is saved to a [`RoomStore`].
+///
+/// [`Room`]: super::Room
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RoomSave {
+    pub config: RoomConfig,
+    pub canvases: HashMap<u16, RoomCanvas>,
+    pub chat: VecDeque<ChatMessage>,
+}
+
+/// Information about a room in a [`RoomStore`], without having to load it.
+#[derive(Debug)]
+pub struct StoredRoom {
+    /// The name of the room.
+    pub name: String,
+    /// When the room was last saved.
+    pub saved_at: SystemTime,
+}
+
+#[derive(Debug)]
+pub enum StoreError {
+    Io(io::Error),
+    Sqlite(rusqlite::Error),
+    /// The room was saved, but the data can't be understood.
+    Corrupt(SnapshotError),
+}
+impl Display for StoreError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StoreError::Io(e) => write!(f, "io error: {e}"),
+            StoreError::Sqlite(e) => write!(f, "sqlite error: {e}"),
+            StoreError::Corrupt(e) => write!(f, "corrupt room data: {e}"),
+        }
+    }
+}
+impl From<io::Error> for StoreError {
+    fn from(value: io::Error) -> Self {
+        StoreError::Io(value)
+    }
+}
+impl From<rusqlite::Error> for StoreError {
+    fn from(value: rusqlite::Error) -> Self {
+        StoreError::Sqlite(value)
+    }
+}
+impl From<SnapshotError> for StoreError {
+    fn from(value: SnapshotError) -> Self {
+        StoreError::Corrupt(value)
+    }
+}
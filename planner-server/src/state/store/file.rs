@@ -0,0 +1,124 @@
+use super::{snapshot, RoomSave, RoomStore, StoreError, StoredRoom};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+use tracing::error;
+
+/// The extension of room files.
+const EXTENSION: &str = "room";
+/// The extension rooms were saved with before they were saved as
+/// [snapshots](snapshot). These are still loaded, and are replaced the next
+/// time the room is saved.
+const LEGACY_EXTENSION: &str = "json";
+
+/// Saves each room to its own file in a directory, as a [snapshot].
+#[derive(Debug)]
+pub struct FileStore {
+    dir: PathBuf,
+}
+impl FileStore {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    /// Returns the path a room with the given name is saved to.
+    ///
+    /// Room names can contain any character, so they are percent-encoded to
+    /// produce a safe file name.
+    fn room_path(&self, name: &str, extension: &str) -> PathBuf {
+        let file_name: String = url::form_urlencoded::byte_serialize(name.as_bytes()).collect();
+        self.dir.join(format!("{file_name}.{extension}"))
+    }
+
+    /// The inverse of [`FileStore::room_path`].
+    fn room_name(path: &Path) -> Option<String> {
+        if path
+            .extension()
+            .is_none_or(|ext| ext != EXTENSION && ext != LEGACY_EXTENSION)
+        {
+            return None;
+        }
+        let stem = path.file_stem()?.to_str()?;
+        url::form_urlencoded::parse(stem.as_bytes())
+            .next()
+            .map(|(name, _)| name.into_owned())
+    }
+}
+impl RoomStore for FileStore {
+    fn load(&self, name: &str) -> Result<Option<RoomSave>, StoreError> {
+        let mut path = self.room_path(name, EXTENSION);
+        let data = match fs::read(&path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                path = self.room_path(name, LEGACY_EXTENSION);
+                match fs::read(&path) {
+                    Ok(data) => data,
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+                    Err(e) => return Err(e.into()),
+                }
+            }
+            Err(e) => return Err(e.into()),
+        };
+        match snapshot::decode(&data) {
+            Ok(room) => Ok(Some(room)),
+            Err(e) => {
+                // Move it aside so it doesn't get overwritten when the room
+                // is next saved.
+                let corrupt_path = path.with_extension("corrupt");
+                error!("Moving corrupt room `{name}` to {corrupt_path:?}");
+                let _ = fs::rename(&path, &corrupt_path)
+                    .inspect_err(|e| error!("Failed to move corrupt room: {e}"));
+                Err(e.into())
+            }
+        }
+    }
+
+    fn save(&self, name: &str, room: &RoomSave) -> Result<(), StoreError> {
+        fs::create_dir_all(&self.dir)?;
+        // Write to a temporary file first so a crash midway through can't
+        // leave a half-written file behind.
+        let path = self.room_path(name, EXTENSION);
+        let tmp_path = path.with_extension("tmp");
+        fs::write(&tmp_path, snapshot::encode(room)?)?;
+        fs::rename(&tmp_path, path)?;
+
+        match fs::remove_file(self.room_path(name, LEGACY_EXTENSION)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    fn list(&self) -> Result<Vec<StoredRoom>, StoreError> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut rooms = vec![];
+        for entry in entries {
+            let entry = entry?;
+            let Some(name) = Self::room_name(&entry.path()) else {
+                continue;
+            };
+            rooms.push(StoredRoom {
+                name,
+                saved_at: entry.metadata()?.modified()?,
+            });
+        }
+        Ok(rooms)
+    }
+
+    fn delete(&self, name: &str) -> Result<bool, StoreError> {
+        let mut deleted = false;
+        for extension in [EXTENSION, LEGACY_EXTENSION] {
+            match fs::remove_file(self.room_path(name, extension)) {
+                Ok(()) => deleted = true,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(deleted)
+    }
+}
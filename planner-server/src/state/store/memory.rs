@@ -0,0 +1,48 @@
+use super::{RoomSave, RoomStore, StoreError, StoredRoom};
+use std::{collections::HashMap, sync::Mutex, time::SystemTime};
+
+/// Keeps saved rooms in memory. Everything is lost when the server stops.
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    rooms: Mutex<HashMap<String, (SystemTime, RoomSave)>>,
+}
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+impl RoomStore for MemoryStore {
+    fn load(&self, name: &str) -> Result<Option<RoomSave>, StoreError> {
+        Ok(self
+            .rooms
+            .lock()
+            .unwrap()
+            .get(name)
+            .map(|(_, room)| room.clone()))
+    }
+
+    fn save(&self, name: &str, room: &RoomSave) -> Result<(), StoreError> {
+        self.rooms
+            .lock()
+            .unwrap()
+            .insert(name.to_string(), (SystemTime::now(), room.clone()));
+        Ok(())
+    }
+
+    fn list(&self) -> Result<Vec<StoredRoom>, StoreError> {
+        Ok(self
+            .rooms
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(name, (saved_at, _))| StoredRoom {
+                name: name.clone(),
+                saved_at: *saved_at,
+            })
+            .collect())
+    }
+
+    fn delete(&self, name: &str) -> Result<bool, StoreError> {
+        Ok(self.rooms.lock().unwrap().remove(name).is_some())
+    }
+}
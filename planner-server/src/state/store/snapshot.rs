@@ -0,0 +1,325 @@
+//! The format rooms are saved in.
+//!
+//! A snapshot is a small header followed by the [`RoomSave`] as
+//! zstd-compressed JSON:
+//!
+//! | Bytes | Contents                                                  |
+//! | ----- | --------------------------------------------------------- |
+//! | 0-3   | [`MAGIC`]                                                 |
+//! | 4-5   | The snapshot version, as a little-endian `u16`            |
+//! | 6-7   | The protocol version of the server that wrote it, as a    |
+//! |       | little-endian `u16`. Only informational.                  |
+//! | 8..   | The compressed JSON body                                  |
+//!
+//! Whenever [`PROTOCOL_VERSION`] or the layout of anything saved in a room
+//! (including [`Element`]) changes, bump [`SNAPSHOT_VERSION`] and add a step to
+//! [`MIGRATIONS`] that upgrades the JSON body from the previous version.
+//!
+//! [`PROTOCOL_VERSION`]: crate::PROTOCOL_VERSION
+//! [`Element`]: crate::state::element::Element
+
+use super::RoomSave;
+use crate::state::{password, RoomCanvas};
+use serde_json::{json, Map, Value};
+use std::{collections::HashMap, fmt::Display, io};
+
+/// The bytes every snapshot starts with.
+pub const MAGIC: &[u8; 4] = b"S3PR";
+/// The version of snapshots written by this server.
+pub const SNAPSHOT_VERSION: u16 = 8;
+/// How hard to compress snapshots. zstd's default level is a good balance
+/// between speed and size.
+const COMPRESSION_LEVEL: i32 = 3;
+const HEADER_LEN: usize = 8;
+
+/// The steps to upgrade a snapshot's body to the current version.
+/// `MIGRATIONS[n]` upgrades a body from version `n` to version `n + 1`.
+///
+/// Room exports go through these too but don't have a `config`, so any
+/// changes to it must be skipped if it's missing.
+const MIGRATIONS: [fn(&mut Value); SNAPSHOT_VERSION as usize] = [
+    v0_to_v1, v1_to_v2, v2_to_v3, v3_to_v4, v4_to_v5, v5_to_v6, v6_to_v7, v7_to_v8,
+];
+
+/// Version 0 was plain JSON without a header or compression. The body itself
+/// didn't change.
+fn v0_to_v1(_: &mut Value) {}
+/// Version 2 added `export_admin_only` to the room config.
+fn v1_to_v2(body: &mut Value) {
+    if let Some(config) = body.get_mut("config").and_then(Value::as_object_mut) {
+        config.insert("export_admin_only".to_string(), Value::Bool(false));
+    }
+}
+
+/// Version 3 added a `version` to each element.
+fn v2_to_v3(body: &mut Value) {
+    for el in elements_mut(body) {
+        el.insert("version".to_string(), Value::from(0));
+    }
+}
+/// Version 4 added who created each element and when, and when it was last
+/// edited. These aren't known for older elements.
+fn v3_to_v4(body: &mut Value) {
+    for el in elements_mut(body) {
+        el.insert("created_by".to_string(), Value::Null);
+        el.insert("created_at".to_string(), Value::from(0));
+        el.insert("edited_at".to_string(), Value::from(0));
+    }
+}
+/// Version 5 added the list of banned users to the room config.
+fn v4_to_v5(body: &mut Value) {
+    if let Some(config) = body.get_mut("config").and_then(Value::as_object_mut) {
+        config.insert("bans".to_string(), Value::Array(vec![]));
+    }
+}
+/// Version 6 replaced the plaintext room password with a hash of it.
+fn v5_to_v6(body: &mut Value) {
+    if let Some(config) = body.get_mut("config").and_then(Value::as_object_mut) {
+        let password_hash = match config.remove("password") {
+            Some(Value::String(password)) => Value::String(password::hash(&password)),
+            _ => Value::Null,
+        };
+        config.insert("password_hash".to_string(), password_hash);
+    }
+}
+/// Version 7 added the chat history, and whether view-only users can chat to
+/// the room config.
+fn v6_to_v7(body: &mut Value) {
+    if let Some(body) = body.as_object_mut() {
+        body.insert("chat".to_string(), Value::Array(vec![]));
+    }
+    if let Some(config) = body.get_mut("config").and_then(Value::as_object_mut) {
+        config.insert("view_only_chat".to_string(), Value::Bool(false));
+    }
+}
+
+/// Version 8 added comment threads to each canvas.
+fn v7_to_v8(body: &mut Value) {
+    for canvas in canvases_mut(body) {
+        canvas.insert("threads".to_string(), Value::Array(vec![]));
+    }
+}
+
+/// Returns every canvas of a snapshot body.
+fn canvases_mut(body: &mut Value) -> impl Iterator<Item = &mut Map<String, Value>> {
+    body.get_mut("canvases")
+        .and_then(Value::as_object_mut)
+        .into_iter()
+        .flat_map(|canvases| canvases.values_mut())
+        .filter_map(Value::as_object_mut)
+}
+/// Returns every element in every canvas of a snapshot body.
+fn elements_mut(body: &mut Value) -> impl Iterator<Item = &mut Map<String, Value>> {
+    canvases_mut(body)
+        .filter_map(|canvas| canvas.get_mut("elements").and_then(Value::as_array_mut))
+        .flatten()
+        .filter_map(Value::as_object_mut)
+}
+
+/// Encodes a room as a snapshot.
+pub fn encode(room: &RoomSave) -> Result<Vec<u8>, SnapshotError> {
+    let body = serde_json::to_vec(room)?;
+    let mut data = Vec::with_capacity(HEADER_LEN + body.len() / 4);
+    data.extend_from_slice(MAGIC);
+    data.extend_from_slice(&SNAPSHOT_VERSION.to_le_bytes());
+    data.extend_from_slice(&(crate::PROTOCOL_VERSION as u16).to_le_bytes());
+    zstd::stream::copy_encode(body.as_slice(), &mut data, COMPRESSION_LEVEL)
+        .map_err(SnapshotError::Compression)?;
+    Ok(data)
+}
+
+/// Decodes a snapshot of any version, migrating it to the current version.
+pub fn decode(data: &[u8]) -> Result<RoomSave, SnapshotError> {
+    let (version, body) = match data.strip_prefix(MAGIC) {
+        Some(rest) if rest.len() >= HEADER_LEN - MAGIC.len() => {
+            let version = u16::from_le_bytes([rest[0], rest[1]]);
+            let body = zstd::decode_all(&rest[4..]).map_err(SnapshotError::Compression)?;
+            (version, body)
+        }
+        Some(_) => return Err(SnapshotError::Truncated),
+        None => (0, data.to_vec()),
+    };
+
+    let body = serde_json::from_slice(&body)?;
+    Ok(serde_json::from_value(migrate(version, body)?)?)
+}
+
+/// Upgrades a JSON body from the given snapshot version to the current one.
+pub fn migrate(version: u16, mut body: Value) -> Result<Value, SnapshotError> {
+    if version > SNAPSHOT_VERSION {
+        return Err(SnapshotError::UnknownVersion { version });
+    }
+    for migration in &MIGRATIONS[version as usize..] {
+        migration(&mut body);
+    }
+    Ok(body)
+}
+
+/// Upgrades the canvases of a room export from the given snapshot version to
+/// the current one.
+///
+/// An export is a snapshot body without the room's config, so it goes
+/// through the same migrations.
+pub fn migrate_canvases(
+    version: u16,
+    canvases: Value,
+) -> Result<HashMap<u16, RoomCanvas>, SnapshotError> {
+    let mut body = migrate(version, json!({ "canvases": canvases }))?;
+    Ok(serde_json::from_value(body["canvases"].take())?)
+}
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    /// The header was cut off.
+    Truncated,
+    /// The snapshot was written by a newer version of the server.
+    UnknownVersion {
+        version: u16,
+    },
+    Compression(io::Error),
+    Json(serde_json::Error),
+}
+impl Display for SnapshotError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SnapshotError::Truncated => write!(f, "snapshot header is truncated"),
+            SnapshotError::UnknownVersion { version } => write!(
+                f,
+                "snapshot version {version} is newer than the supported version {SNAPSHOT_VERSION}"
+            ),
+            SnapshotError::Compression(e) => write!(f, "failed to (de)compress snapshot: {e}"),
+            SnapshotError::Json(e) => write!(f, "invalid snapshot body: {e}"),
+        }
+    }
+}
+impl From<serde_json::Error> for SnapshotError {
+    fn from(value: serde_json::Error) -> Self {
+        SnapshotError::Json(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::{
+        chat::ChatMessage,
+        element::{Element, ElementText, ElementType},
+        RoomConfig,
+    };
+    use uuid::Uuid;
+
+    fn room() -> RoomSave {
+        let mut canvas = RoomCanvas::default();
+        canvas
+            .elements
+            .push(Element::new(ElementType::Text(ElementText::new(
+                "hello".to_string(),
+            ))));
+        RoomSave {
+            config: RoomConfig::new(None),
+            canvases: HashMap::from([(0, canvas)]),
+            chat: [ChatMessage {
+                uuid: Uuid::new_v4(),
+                user: Uuid::new_v4(),
+                username: "user".to_string(),
+                content: "hi".to_string(),
+                sent_at: 1,
+            }]
+            .into(),
+        }
+    }
+
+    /// A room saved by the first version of the server, which wrote plain
+    /// JSON.
+    const V0_ROOM: &str = r##"{
+        "config": { "new_users_default_editor": true, "password": "hunter2" },
+        "canvases": {
+            "3": {
+                "elements": [{
+                    "uuid": "11111111-1111-4111-8111-111111111111",
+                    "ty": {
+                        "type": "text",
+                        "content": "old",
+                        "align": "left",
+                        "color": "#ff0000ff",
+                        "size": 30,
+                        "font": { "font_type": "sans", "custom_font_family": null },
+                        "background_color": "#00000000",
+                        "background_blur": 0
+                    },
+                    "last_edited_by": null,
+                    "selected_by": null,
+                    "x": 1,
+                    "y": 2,
+                    "anchor": { "top": 0.5, "left": 0.5 },
+                    "rotation": 0,
+                    "scale_rate": "none",
+                    "z_index": 0,
+                    "tags": ["a"]
+                }]
+            }
+        }
+    }"##;
+
+    #[test]
+    fn encode_then_decode() {
+        let room = room();
+        let data = encode(&room).unwrap();
+        assert!(data.starts_with(MAGIC));
+        assert_eq!(data[4..6], SNAPSHOT_VERSION.to_le_bytes());
+
+        let decoded = decode(&data).unwrap();
+        assert_eq!(
+            serde_json::to_value(&decoded).unwrap(),
+            serde_json::to_value(&room).unwrap()
+        );
+    }
+
+    #[test]
+    fn decode_v0() {
+        let room = decode(V0_ROOM.as_bytes()).unwrap();
+
+        let config = &room.config;
+        assert!(config.new_users_default_editor);
+        assert!(!config.export_admin_only);
+        assert!(!config.view_only_chat);
+        assert!(config.bans.is_empty());
+        let password_hash = config.password_hash.as_deref().unwrap();
+        assert!(password::verify(password_hash, "hunter2"));
+        assert!(room.chat.is_empty());
+
+        let canvas = &room.canvases[&3];
+        assert!(canvas.threads.is_empty());
+        let el = &canvas.elements[0];
+        assert_eq!(el.uuid.to_string(), "11111111-1111-4111-8111-111111111111");
+        assert_eq!(el.version, Some(0));
+        assert_eq!(el.created_by, None);
+        assert_eq!(el.created_at, 0);
+        assert_eq!((el.x, el.y), (1., 2.));
+        assert!(el.tags.contains("a"));
+    }
+
+    #[test]
+    fn decode_truncated_header() {
+        let data = [MAGIC.as_slice(), &SNAPSHOT_VERSION.to_le_bytes()].concat();
+        assert!(matches!(decode(&data), Err(SnapshotError::Truncated)));
+    }
+
+    #[test]
+    fn decode_newer_version() {
+        let mut data = encode(&room()).unwrap();
+        data[4..6].copy_from_slice(&(SNAPSHOT_VERSION + 1).to_le_bytes());
+        assert!(matches!(
+            decode(&data),
+            Err(SnapshotError::UnknownVersion { version }) if version == SNAPSHOT_VERSION + 1
+        ));
+    }
+
+    #[test]
+    fn migrate_old_export() {
+        let v0: Value = serde_json::from_str(V0_ROOM).unwrap();
+        let canvases = migrate_canvases(0, v0["canvases"].clone()).unwrap();
+        assert_eq!(canvases[&3].elements.len(), 1);
+        assert!(canvases[&3].threads.is_empty());
+    }
+}
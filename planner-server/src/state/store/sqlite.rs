@@ -0,0 +1,81 @@
+use super::{snapshot, RoomSave, RoomStore, StoreError, StoredRoom};
+use rusqlite::{params, Connection, OptionalExtension};
+use std::{
+    path::Path,
+    sync::Mutex,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Saves rooms to an embedded SQLite database, as [snapshots](snapshot).
+#[derive(Debug)]
+pub struct SqliteStore {
+    conn: Mutex<Connection>,
+}
+impl SqliteStore {
+    /// Opens the database at the given path, creating it if it doesn't exist.
+    pub fn open(path: &Path) -> Result<Self, StoreError> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let conn = Connection::open(path)?;
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS rooms (
+                name TEXT PRIMARY KEY NOT NULL,
+                saved_at INTEGER NOT NULL,
+                data BLOB NOT NULL
+            )",
+        )?;
+        Ok(Self {
+            conn: Mutex::new(conn),
+        })
+    }
+}
+impl RoomStore for SqliteStore {
+    fn load(&self, name: &str) -> Result<Option<RoomSave>, StoreError> {
+        let data: Option<Vec<u8>> = self
+            .conn
+            .lock()
+            .unwrap()
+            .query_row("SELECT data FROM rooms WHERE name = ?1", [name], |row| {
+                row.get(0)
+            })
+            .optional()?;
+        Ok(data.map(|data| snapshot::decode(&data)).transpose()?)
+    }
+
+    fn save(&self, name: &str, room: &RoomSave) -> Result<(), StoreError> {
+        let saved_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs() as i64;
+        self.conn.lock().unwrap().execute(
+            "INSERT INTO rooms (name, saved_at, data) VALUES (?1, ?2, ?3)
+            ON CONFLICT (name) DO UPDATE SET saved_at = ?2, data = ?3",
+            params![name, saved_at, snapshot::encode(room)?],
+        )?;
+        Ok(())
+    }
+
+    fn list(&self) -> Result<Vec<StoredRoom>, StoreError> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT name, saved_at FROM rooms")?;
+        let rooms = stmt
+            .query_map([], |row| {
+                Ok(StoredRoom {
+                    name: row.get(0)?,
+                    saved_at: UNIX_EPOCH + Duration::from_secs(row.get::<_, i64>(1)? as u64),
+                })
+            })?
+            .collect::<Result<_, _>>()?;
+        Ok(rooms)
+    }
+
+    fn delete(&self, name: &str) -> Result<bool, StoreError> {
+        let deleted = self
+            .conn
+            .lock()
+            .unwrap()
+            .execute("DELETE FROM rooms WHERE name = ?1", [name])?;
+        Ok(deleted > 0)
+    }
+}
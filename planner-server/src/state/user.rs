@@ -1,4 +1,4 @@
-use std::fmt::Display;
+use std::{fmt::Display, time::UNIX_EPOCH};
 
 use super::{Color, RoomUser};
 use serde::{Deserialize, Serialize};
@@ -11,6 +11,11 @@ pub struct User {
     pub canvas: u16,
     pub uuid: Uuid,
     pub access_level: AccessLevel,
+    /// If this is the admin and they disconnected, when they'll stop being the
+    /// admin if they don't come back, in milliseconds since the Unix epoch.
+    pub admin_held_until: Option<u64>,
+    /// Whether they're following the presenter.
+    pub following: bool,
 }
 impl From<RoomUser> for User {
     fn from(value: RoomUser) -> Self {
@@ -20,10 +25,63 @@ impl From<RoomUser> for User {
             canvas: value.canvas,
             uuid: value.uuid,
             access_level: value.access_level,
+            admin_held_until: value.admin_held_until.map(|until| {
+                until
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |d| d.as_millis() as u64)
+            }),
+            following: value.following,
         }
     }
 }
 
+/// Where a user is looking on their canvas. This is only ever passed on to
+/// other users on the same canvas, and is never saved.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Presence {
+    /// Where the user's cursor is on the canvas, or `None` if it isn't over
+    /// it.
+    pub cursor: Option<Position>,
+    pub viewport: Viewport,
+}
+impl Presence {
+    /// Whether every number is finite and the zoom is positive.
+    pub fn is_valid(&self) -> bool {
+        self.cursor
+            .as_ref()
+            .is_none_or(|c| c.x.is_finite() && c.y.is_finite())
+            && self.viewport.x.is_finite()
+            && self.viewport.y.is_finite()
+            && self.viewport.zoom.is_finite()
+            && self.viewport.zoom > 0.
+    }
+}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Position {
+    pub x: f64,
+    pub y: f64,
+}
+/// The part of the canvas a user can see.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Viewport {
+    /// The offset of the top-left corner.
+    pub x: f64,
+    pub y: f64,
+    pub zoom: f64,
+}
+/// A user's latest [`Presence`].
+#[derive(Debug, Serialize)]
+pub struct UserPresence {
+    pub user: Uuid,
+    #[serde(flatten)]
+    pub presence: Presence,
+}
+
+/// Creates a new secret token a user can resume their session with.
+pub fn new_resume_token() -> String {
+    Uuid::new_v4().simple().to_string()
+}
+
 #[derive(
     Debug, Eq, PartialEq, Ord, PartialOrd, Clone, Copy, Default, Hash, Serialize, Deserialize,
 )]
@@ -47,3 +105,32 @@ impl Display for AccessLevel {
         )
     }
 }
+
+/// Why the server disconnected a user who was already in a room.
+///
+/// This is sent as the reason of the websocket close frame, the same way
+/// errors joining a room are.
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum DisconnectReason {
+    /// The admin closed the room.
+    RoomClosed,
+    /// The admin kicked them from the room. They can rejoin.
+    Kicked { by: Uuid },
+    /// The admin banned them from the room. They can't rejoin, unless only
+    /// their session was banned.
+    Banned { by: Uuid },
+}
+impl DisconnectReason {
+    /// The websocket close code for this reason.
+    ///
+    /// These are all `41xx`, to keep them apart from the `40xx` codes used
+    /// when failing to join a room.
+    pub fn close_code(&self) -> u16 {
+        match self {
+            DisconnectReason::RoomClosed => 4100,
+            DisconnectReason::Kicked { .. } => 4101,
+            DisconnectReason::Banned { .. } => 4102,
+        }
+    }
+}